@@ -0,0 +1,50 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+#![no_main]
+
+use bincode::{config, BorrowDecode, Decode, Encode};
+use libfuzzer_sys::fuzz_target;
+use packet::BoundVec;
+
+/// Decode the data both with [`Decode`] and [`BorrowDecode`], make sure
+/// the results are the same, and that they encode back to the original
+/// bytes.
+fn check<T>(data: &[u8])
+where
+    T: Encode + Decode + for<'a> BorrowDecode<'a> + PartialEq + std::fmt::Debug,
+{
+    let decoded = bincode::decode_from_slice::<T, _>(data, config::legacy());
+    let borrowed = bincode::borrow_decode_from_slice::<T, _>(data, config::legacy());
+    let (obj, len) = match (decoded, borrowed) {
+        (Ok((decoded, decoded_len)), Ok((borrowed, len))) => {
+            assert_eq!(decoded, borrowed);
+            assert_eq!(decoded_len, len);
+            (borrowed, len)
+        }
+        (Err(_), Err(_)) => return,
+        (decoded, borrowed) => {
+            panic!("Decode mismatch: {decoded:?} vs {borrowed:?}");
+        }
+    };
+
+    let encoded = bincode::encode_to_vec(&obj, config::legacy()).unwrap();
+    assert_eq!(encoded, data[..len]);
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((kind, data)) = data.split_first() else {
+        return;
+    };
+
+    match kind % 8 {
+        0 => check::<BoundVec<0, u8>>(data),
+        1 => check::<BoundVec<0, u32>>(data),
+        2 => check::<BoundVec<1, u8>>(data),
+        3 => check::<BoundVec<2, u16>>(data),
+        4 => check::<BoundVec<4, u8>>(data),
+        5 => check::<BoundVec<8, u64>>(data),
+        6 => check::<BoundVec<0, BoundVec<4, u8>>>(data),
+        _ => check::<BoundVec<1, BoundVec<2, u8>>>(data),
+    }
+});
@@ -0,0 +1,20 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use packet::Header;
+
+fuzz_target!(|data: &[u8]| {
+    for checksum in [true, false] {
+        let Ok(hdr) = Header::deserialize(data, checksum) else {
+            continue;
+        };
+
+        let len = Header::num_bytes(checksum);
+        let mut buf = vec![0u8; len];
+        assert_eq!(hdr.serialize(&mut buf).unwrap(), len);
+        assert_eq!(buf, data[..len]);
+    }
+});
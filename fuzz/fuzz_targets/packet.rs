@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use packet::pkt_common::ServiceID;
+use packet::{Header, Packet, PacketContext, Payload};
+
+// Input: u16 packet ID, sender & receiver ServiceID bytes, then the payload
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+    let id = u16::from_le_bytes([data[0], data[1]]);
+    let service = |byte: u8| ServiceID::try_from(byte).ok();
+    let ctx = PacketContext::new(service(data[2]), service(data[3]));
+    let payload = &data[4..];
+
+    for p in [
+        Packet::deserialize_no_hdr(id, payload),
+        Packet::deserialize_no_hdr_ctx(&ctx, id, payload),
+    ] {
+        let Ok(p) = p else {
+            continue;
+        };
+        assert_eq!(p.id(), id);
+
+        // serialize(deserialize(x)) == x
+        let mut buf = Vec::new();
+        p.serialize_no_hdr(&mut buf).unwrap();
+        assert_eq!(buf, payload, "{p:?}");
+
+        // and with a header on top
+        for checksum in [true, false] {
+            let mut buf = Vec::new();
+            let Ok(len) = p.serialize(&mut buf, checksum) else {
+                // payload too long to fit in u16
+                continue;
+            };
+            assert_eq!(len, buf.len());
+            let hdr = Header::deserialize(&buf, checksum).unwrap();
+            assert_eq!((hdr.id, hdr.len as usize), (id, len));
+            assert_eq!(buf[Header::num_bytes(checksum)..], *payload);
+        }
+    }
+});
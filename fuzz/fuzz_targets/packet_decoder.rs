@@ -0,0 +1,43 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use server::packet_stream::{PacketDecodeResult, PacketDecoder};
+
+// Input: u32 xor table seed, u16 xor key index, checksum flag, then encoded
+// packets
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 7 {
+        return;
+    }
+    let seed = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    let key_idx = u16::from_le_bytes(data[4..6].try_into().unwrap());
+    let mut decoder = PacketDecoder::new(Some(seed), Some(key_idx));
+    decoder.rx_checksum = data[6] & 0x1 != 0;
+
+    // decode the packets just like PacketStream does: the length first,
+    // then the entire packet
+    let mut data = data[7..].to_vec();
+    let mut data = &mut data[..];
+    while data.len() >= 4 {
+        let len = match decoder.decode(&mut data[..4]) {
+            Ok(PacketDecodeResult::PayloadIncomplete(len)) => len as usize,
+            Ok(r) => panic!("Unexpected result for a partial header: {r:?}"),
+            Err(_) => break,
+        };
+        if len > data.len() {
+            break;
+        }
+
+        match decoder.decode(&mut data[..len]) {
+            Ok(PacketDecodeResult::Done(decoded_len)) => {
+                assert_eq!(decoded_len as usize, len);
+            }
+            Ok(r) => panic!("Unexpected result for a full packet: {r:?}"),
+            Err(_) => break,
+        }
+        data = &mut data[len..];
+    }
+});
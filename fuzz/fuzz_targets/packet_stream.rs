@@ -0,0 +1,60 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+#![no_main]
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::AsyncRead;
+use libfuzzer_sys::fuzz_target;
+use server::packet_stream::{ChecksumMode, PacketStream, StreamConfig};
+
+/// In-memory reader that returns at most `chunk_len` bytes per read, so
+/// that packets are received in pieces just like over TCP
+struct ChunkedReader<'a> {
+    data: &'a [u8],
+    chunk_len: usize,
+}
+
+impl AsyncRead for ChunkedReader<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let len = buf.len().min(self.chunk_len).min(self.data.len());
+        buf[..len].copy_from_slice(&self.data[..len]);
+        self.data = &self.data[len..];
+        Poll::Ready(Ok(len))
+    }
+}
+
+// Input: config flags, read chunk length, then the raw stream data
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let flags = data[0];
+    let reader = ChunkedReader {
+        data: &data[2..],
+        chunk_len: data[1].max(1) as usize,
+    };
+
+    let mut config = StreamConfig::ipc("fuzz".into(), "input".into());
+    config.deserialize_checksum = flags & 0x1 != 0;
+    config.decode_rx = flags & 0x2 != 0;
+    if flags & 0x4 != 0 {
+        config.checksum_mode = ChecksumMode::Lenient;
+    }
+    if flags & 0x8 != 0 {
+        config.max_pkt_len = 0x100;
+    }
+    let mut stream = PacketStream::new(reader, config);
+
+    futures::executor::block_on(async {
+        // read until the data ends or turns out malformed
+        while stream.recv().await.is_ok() {}
+    });
+});
@@ -0,0 +1,24 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use packet::{Header, Packet, PayloadSerializeError};
+
+// deserialize(serialize(p)) == p for arbitrary, valid packets
+fuzz_target!(|input: (Packet, bool)| {
+    let (p, checksum) = input;
+    let mut buf = Vec::new();
+    let len = match p.serialize(&mut buf, checksum) {
+        Ok(len) => len,
+        Err(PayloadSerializeError::PayloadTooLong { .. }) => return,
+        Err(e) => panic!("Can't serialize {p:?}: {e}"),
+    };
+    assert_eq!(len, buf.len());
+
+    let hdr = Header::deserialize(&buf, checksum).unwrap();
+    let payload = &buf[Header::num_bytes(checksum)..];
+    let p2 = Packet::deserialize_no_hdr_ctx(&p.context(), hdr.id, payload).unwrap();
+    assert_eq!(p, p2);
+});
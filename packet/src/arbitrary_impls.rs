@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+//! [`Arbitrary`] support for the helper types, used by round-trip tests
+//! and fuzzing.
+//!
+//! The generated values always serialize back to the same value, e.g.
+//! strings never contain NUL characters and vectors are never too long
+//! for their length prefix.
+
+use std::fmt::Debug;
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{Arr, Block, BoundVec, NulltermString};
+
+impl<'a> Arbitrary<'a> for Block {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Block::from(<[u8; 16]>::arbitrary(u)?))
+    }
+}
+
+impl<'a, const S: usize, T: Arbitrary<'a>> Arbitrary<'a> for BoundVec<S, T> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let max_len = match S {
+            1 => u8::MAX as usize,
+            2 => u16::MAX as usize,
+            4 => u32::MAX as usize,
+            _ => usize::MAX,
+        };
+        let len = u.arbitrary_len::<T>()?.min(max_len);
+        let vec = (0..len)
+            .map(|_| T::arbitrary(u))
+            .collect::<Result<Vec<T>>>()?;
+        Ok(BoundVec(vec))
+    }
+}
+
+impl<'a> Arbitrary<'a> for NulltermString {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let str = String::arbitrary(u)?;
+        Ok(NulltermString(str.replace('\0', "")))
+    }
+}
+
+impl<'a, T, const N: usize> Arbitrary<'a> for Arr<T, N>
+where
+    T: Arbitrary<'a> + Debug + 'static,
+{
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut ret = Arr::<T, N>::default();
+        for item in ret.iter_mut() {
+            *item = T::arbitrary(u)?;
+        }
+        Ok(ret)
+    }
+}
@@ -4,18 +4,24 @@
 use std::{
     any::TypeId,
     fmt::Debug,
-    mem::{size_of, MaybeUninit},
+    mem::MaybeUninit,
     ops::{Deref, DerefMut},
 };
 
 use anyhow::Result;
 use bincode::{
-    de::{read::Reader, BorrowDecoder},
+    config,
+    de::{
+        read::{BorrowReader, Reader},
+        BorrowDecoder, Decoder,
+    },
     enc::write::Writer,
     error::{DecodeError, EncodeError},
     BorrowDecode, Decode, Encode,
 };
 
+use crate::FixedSize;
+
 pub use aria::BlockSlice;
 
 /// Encode-able wrapper for Block
@@ -24,6 +30,10 @@ pub use aria::BlockSlice;
 pub struct Block([u8; 16]);
 impl aria::BlockExt for Block {}
 
+impl FixedSize for Block {
+    const FIXED_SIZE: Option<usize> = Some(16);
+}
+
 impl AsRef<[u8; 16]> for Block {
     fn as_ref(&self) -> &[u8; 16] {
         &self.0
@@ -74,8 +84,50 @@ impl Decode for Block {
 }
 
 impl<'a> BorrowDecode<'a> for Block {
-    fn borrow_decode<D: BorrowDecoder<'a>>(_: &mut D) -> Result<Self, DecodeError> {
-        unimplemented!();
+    fn borrow_decode<D: BorrowDecoder<'a>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let bytes = decoder.borrow_reader().take_bytes(16)?;
+        Ok(Block(bytes.try_into().unwrap()))
+    }
+}
+
+/// Number of bytes left in the source, or None if the reader can't peek.
+/// Nothing is consumed.
+fn remaining_len<R: Reader>(reader: &mut R) -> Option<usize> {
+    // peek_read(n) gives n bytes only if that many are left, so search
+    // for the biggest n that still succeeds
+    reader.peek_read(0)?;
+    let (mut available, mut missing) = (0usize, 1usize);
+    while reader.peek_read(missing).is_some() {
+        available = missing;
+        missing = missing.checked_mul(2)?;
+    }
+    while missing - available > 1 {
+        let mid = available + (missing - available) / 2;
+        match reader.peek_read(mid) {
+            Some(_) => available = mid,
+            None => missing = mid,
+        }
+    }
+    Some(available)
+}
+
+/// Read all remaining bytes, in chunks, from a reader that can't tell
+/// how many there are
+fn read_to_end<R: Reader>(reader: &mut R) -> Result<Vec<u8>, DecodeError> {
+    let mut buf = Vec::new();
+    loop {
+        let pos = buf.len();
+        buf.resize(pos + CHUNK_SIZE, 0);
+        match reader.read(&mut buf[pos..]) {
+            Ok(()) => {}
+            Err(DecodeError::UnexpectedEnd { additional }) => {
+                debug_assert!(additional <= CHUNK_SIZE);
+                buf.truncate(pos + CHUNK_SIZE - additional);
+                reader.read(&mut buf[pos..])?;
+                return Ok(buf);
+            }
+            Err(err) => return Err(err),
+        }
     }
 }
 
@@ -84,6 +136,10 @@ impl<'a> BorrowDecode<'a> for Block {
 #[derive(PartialEq, Clone, bincode::Encode, bincode::Decode)]
 pub struct Arr<T: 'static, const S: usize>([T; S]);
 
+impl<T: FixedSize, const S: usize> FixedSize for Arr<T, S> {
+    const FIXED_SIZE: Option<usize> = <[T; S]>::FIXED_SIZE;
+}
+
 impl<T: Debug, const S: usize> Deref for Arr<T, S> {
     type Target = [T; S];
 
@@ -123,6 +179,10 @@ impl<T: Copy, const S: usize> From<&[T]> for Arr<T, S> {
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct BoundVec<const S: usize, T>(pub Vec<T>);
 
+impl<const S: usize, T> FixedSize for BoundVec<S, T> {
+    const FIXED_SIZE: Option<usize> = None;
+}
+
 impl<const S: usize, T> Encode for BoundVec<S, T>
 where
     T: Encode + Decode + 'static,
@@ -131,20 +191,24 @@ where
         &self,
         encoder: &mut E,
     ) -> std::result::Result<(), EncodeError> {
+        let len = self.0.len();
+        let overflow = || {
+            EncodeError::OtherString(format!(
+                "BoundVec<{S}, _> length {len} doesn't fit in its prefix"
+            ))
+        };
         match S {
             0 => {}
             1 => encoder
                 .writer()
-                .write(&(self.0.len() as u8).to_le_bytes())?,
+                .write(&u8::try_from(len).map_err(|_| overflow())?.to_le_bytes())?,
             2 => encoder
                 .writer()
-                .write(&(self.0.len() as u16).to_le_bytes())?,
+                .write(&u16::try_from(len).map_err(|_| overflow())?.to_le_bytes())?,
             4 => encoder
                 .writer()
-                .write(&(self.0.len() as u32).to_le_bytes())?,
-            8 => encoder
-                .writer()
-                .write(&(self.0.len() as u64).to_le_bytes())?,
+                .write(&u32::try_from(len).map_err(|_| overflow())?.to_le_bytes())?,
+            8 => encoder.writer().write(&(len as u64).to_le_bytes())?,
             _ => unreachable!(),
         }
 
@@ -164,48 +228,80 @@ where
 
 impl<const S: usize, T> Decode for BoundVec<S, T>
 where
-    T: Encode + Decode + 'static,
+    T: Encode + Decode + for<'a> BorrowDecode<'a> + 'static,
 {
     fn decode<D: bincode::de::Decoder>(decoder: &mut D) -> std::result::Result<Self, DecodeError> {
-        let mut lenbuf = [0u8; S];
-        decoder.reader().read(&mut lenbuf)?;
-
-        let len = match S {
-            0 => {
-                const FULL_PACKET_SIZE: usize = 65536 + 1;
-                let mut unused_buf = [0u8; FULL_PACKET_SIZE];
-                let len = match decoder.reader().read(&mut unused_buf) {
-                    Err(DecodeError::UnexpectedEnd { additional }) => {
-                        debug_assert!(additional <= FULL_PACKET_SIZE);
-                        FULL_PACKET_SIZE - additional
-                    }
-                    Err(err) => return Err(err),
-                    Ok(_) => {
-                        return Err(DecodeError::Other("Data has no end"));
-                    }
-                };
-
-                // round down, ignore the remainder for now
-                len / size_of::<T>()
+        if S == 0 {
+            // There's no way to tell how many bytes are left in a generic
+            // reader, so read everything into a heap buffer first
+            let buf = read_to_end(decoder.reader())?;
+            let (ret, _) = bincode::borrow_decode_from_slice(&buf, config::legacy())?;
+            return Ok(ret);
+        }
+
+        let len = decode_len::<S, D>(decoder)?;
+        Self::decode_n(decoder, len)
+    }
+}
+
+impl<'a, const S: usize, T> BorrowDecode<'a> for BoundVec<S, T>
+where
+    T: Encode + BorrowDecode<'a> + 'static,
+{
+    fn borrow_decode<D: BorrowDecoder<'a>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        if S != 0 {
+            let len = decode_len::<S, D>(decoder)?;
+            return Self::borrow_decode_n(decoder, len);
+        }
+
+        let Some(len) = remaining_len(decoder.borrow_reader()) else {
+            if TypeId::of::<T>() == TypeId::of::<u8>() {
+                let vec = read_to_end(decoder.borrow_reader())?;
+                // Safety: Vec<T> is Vec<u8>
+                return Ok(BoundVec(unsafe {
+                    core::mem::transmute::<Vec<u8>, Vec<T>>(vec)
+                }));
             }
-            1 => u8::from_le_bytes(lenbuf[..1].try_into().unwrap()) as usize,
-            2 => u16::from_le_bytes(lenbuf[..2].try_into().unwrap()) as usize,
-            4 => u32::from_le_bytes(lenbuf[..4].try_into().unwrap()) as usize,
-            8 => u64::from_le_bytes(lenbuf[..8].try_into().unwrap()) as usize,
-            _ => unreachable!(),
+            return Err(DecodeError::Other(
+                "Can't find the end of BoundVec<0, _> without peeking",
+            ));
         };
+        if TypeId::of::<T>() == TypeId::of::<u8>() {
+            return Self::borrow_decode_n(decoder, len);
+        }
+
+        // items may differ in size, so decode them until the end
+        let mut vec = Vec::new();
+        while remaining_len(decoder.borrow_reader()) != Some(0) {
+            vec.push(T::borrow_decode(decoder)?);
+        }
+        Ok(BoundVec(vec))
+    }
+}
 
+impl<const S: usize, T: 'static> BoundVec<S, T> {
+    /// Decode exactly `len` items, ignoring S. Used by `#[packet]` for
+    /// vectors whose length is stored in a separate `#[len_of]` field.
+    #[doc(hidden)]
+    pub fn decode_n<D: Decoder>(decoder: &mut D, len: usize) -> Result<Self, DecodeError>
+    where
+        T: Decode,
+    {
         decoder.claim_container_read::<T>(len)?;
         if TypeId::of::<T>() == TypeId::of::<u8>() {
             decoder.unclaim_bytes_read(len);
-            let mut vec = vec![0u8; len];
-            decoder.reader().read(&mut vec)?;
+            let mut vec = Vec::new();
+            while vec.len() < len {
+                let pos = vec.len();
+                vec.resize(pos + (len - pos).min(CHUNK_SIZE), 0);
+                decoder.reader().read(&mut vec[pos..])?;
+            }
             // Safety: Vec<T> is Vec<u8>
             Ok(BoundVec(unsafe {
                 core::mem::transmute::<Vec<u8>, Vec<T>>(vec)
             }))
         } else {
-            let mut vec = Vec::with_capacity(len);
+            let mut vec = Vec::with_capacity(len.min(CHUNK_SIZE));
             for _ in 0..len {
                 decoder.unclaim_bytes_read(core::mem::size_of::<T>());
                 vec.push(T::decode(decoder)?);
@@ -213,17 +309,54 @@ where
             Ok(BoundVec(vec))
         }
     }
-}
 
-impl<'a, const S: usize, T> BorrowDecode<'a> for BoundVec<S, T>
-where
-    T: Encode + Decode,
-{
-    fn borrow_decode<D: BorrowDecoder<'a>>(_decoder: &mut D) -> Result<Self, DecodeError> {
-        unimplemented!();
+    /// Counterpart of [`Self::decode_n`] for borrowed sources. The bytes
+    /// are taken from the source at once, but still copied into the Vec
+    #[doc(hidden)]
+    pub fn borrow_decode_n<'a, D: BorrowDecoder<'a>>(
+        decoder: &mut D,
+        len: usize,
+    ) -> Result<Self, DecodeError>
+    where
+        T: BorrowDecode<'a>,
+    {
+        decoder.claim_container_read::<T>(len)?;
+        if TypeId::of::<T>() == TypeId::of::<u8>() {
+            decoder.unclaim_bytes_read(len);
+            let vec = decoder.borrow_reader().take_bytes(len)?.to_vec();
+            // Safety: Vec<T> is Vec<u8>
+            Ok(BoundVec(unsafe {
+                core::mem::transmute::<Vec<u8>, Vec<T>>(vec)
+            }))
+        } else {
+            let mut vec = Vec::with_capacity(len.min(CHUNK_SIZE));
+            for _ in 0..len {
+                decoder.unclaim_bytes_read(core::mem::size_of::<T>());
+                vec.push(T::borrow_decode(decoder)?);
+            }
+            Ok(BoundVec(vec))
+        }
     }
 }
 
+/// Decoded lengths come straight from the network, so never trust them
+/// to pre-allocate memory. Grow the buffers by at most this many items.
+const CHUNK_SIZE: usize = 4096;
+
+/// Decode the length prefix of a [`BoundVec`] with S > 0
+fn decode_len<const S: usize, D: Decoder>(decoder: &mut D) -> Result<usize, DecodeError> {
+    let mut lenbuf = [0u8; S];
+    decoder.reader().read(&mut lenbuf)?;
+
+    Ok(match S {
+        1 => u8::from_le_bytes(lenbuf[..1].try_into().unwrap()) as usize,
+        2 => u16::from_le_bytes(lenbuf[..2].try_into().unwrap()) as usize,
+        4 => u32::from_le_bytes(lenbuf[..4].try_into().unwrap()) as usize,
+        8 => u64::from_le_bytes(lenbuf[..8].try_into().unwrap()) as usize,
+        _ => unreachable!(),
+    })
+}
+
 impl<const S: usize, T> Deref for BoundVec<S, T>
 where
     T: Encode + Decode,
@@ -266,6 +399,10 @@ impl<const S: usize> From<&str> for BoundVec<S, u8> {
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct NulltermString(pub String);
 
+impl FixedSize for NulltermString {
+    const FIXED_SIZE: Option<usize> = None;
+}
+
 impl Encode for NulltermString {
     fn encode<E: bincode::enc::Encoder>(
         &self,
@@ -299,8 +436,20 @@ impl Decode for NulltermString {
 }
 
 impl<'a> BorrowDecode<'a> for NulltermString {
-    fn borrow_decode<D: BorrowDecoder<'a>>(_decoder: &mut D) -> Result<Self, DecodeError> {
-        unimplemented!();
+    fn borrow_decode<D: BorrowDecoder<'a>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let reader = decoder.borrow_reader();
+        let Some(bytes) = remaining_len(reader).and_then(|len| reader.peek_read(len)) else {
+            return Self::decode(decoder);
+        };
+        let Some(nul_pos) = bytes.iter().position(|b| *b == 0) else {
+            return Err(DecodeError::UnexpectedEnd { additional: 1 });
+        };
+        let str = std::str::from_utf8(&bytes[..nul_pos])
+            .map_err(|inner| DecodeError::Utf8 { inner })?
+            .to_owned();
+        reader.consume(nul_pos + 1);
+        decoder.claim_bytes_read(nul_pos + 1)?;
+        Ok(Self(str))
     }
 }
 
@@ -338,4 +487,68 @@ mod tests {
     fn block_size() {
         assert_eq!(std::mem::size_of::<Block>(), 16);
     }
+
+    #[derive(Debug, PartialEq, bincode::Encode, bincode::Decode)]
+    struct Mixed {
+        name: NulltermString,
+        block: Block,
+        prefixed: BoundVec<2, u16>,
+        rest: BoundVec<0, u8>,
+    }
+
+    #[test]
+    fn borrow_decode() {
+        let mut data = b"abc\0".to_vec();
+        data.extend(0..16);
+        data.extend([2, 0, 0x34, 0x12, 0x78, 0x56]);
+        data.extend(vec![0xaa; 5000]);
+
+        let mut reader = bincode::de::read::SliceReader::new(&data);
+        assert_eq!(remaining_len(&mut reader), Some(data.len()));
+        reader.consume(5);
+        assert_eq!(remaining_len(&mut reader), Some(data.len() - 5));
+
+        let (m, len) =
+            bincode::borrow_decode_from_slice::<Mixed, _>(&data, config::legacy()).unwrap();
+        assert_eq!(len, data.len());
+        assert_eq!(*m.name, "abc");
+        assert_eq!(m.block[15], 15);
+        assert_eq!(*m.prefixed, vec![0x1234, 0x5678]);
+        assert_eq!(m.rest.len(), 5000);
+
+        // the owned path must give the same result
+        let (m2, len) = bincode::decode_from_slice::<Mixed, _>(&data, config::legacy()).unwrap();
+        assert_eq!(len, data.len());
+        assert_eq!(m, m2);
+
+        // no terminator
+        assert!(
+            bincode::borrow_decode_from_slice::<NulltermString, _>(b"abc", config::legacy())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn bound_vec_len_overflow() {
+        let encode = |v: &dyn Fn(&mut Vec<u8>) -> Result<usize, EncodeError>| {
+            let mut buf = Vec::new();
+            v(&mut buf).map(|_| buf.len())
+        };
+        let vec: BoundVec<1, u8> = vec![0u8; 255].into();
+        let len = encode(&|buf| bincode::encode_into_std_write(&vec, buf, config::legacy()));
+        assert_eq!(len.unwrap(), 256);
+
+        let vec: BoundVec<1, u8> = vec![0u8; 256].into();
+        let len = encode(&|buf| bincode::encode_into_std_write(&vec, buf, config::legacy()));
+        assert!(len.is_err());
+
+        let vec: BoundVec<2, u16> = vec![0u16; 0x10000].into();
+        let len = encode(&|buf| bincode::encode_into_std_write(&vec, buf, config::legacy()));
+        assert!(len.is_err());
+
+        // zero-sized items, so this doesn't allocate
+        let vec: BoundVec<4, ()> = vec![(); u32::MAX as usize + 1].into();
+        let len = encode(&|buf| bincode::encode_into_std_write(&vec, buf, config::legacy()));
+        assert!(len.is_err());
+    }
 }
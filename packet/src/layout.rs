@@ -0,0 +1,215 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+use bincode::enc::write::SizeWriter;
+use bincode::error::{DecodeError, EncodeError};
+use std::fmt::Debug;
+
+/// Position of a single field within a serialized payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldLayout {
+    pub name: &'static str,
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// Implemented by every `#[packet]` struct.
+pub trait PacketLayout {
+    /// Offsets and lengths of all (top-level) fields, as they would be
+    /// serialized. Variable-length fields are measured with their
+    /// current contents.
+    fn layout(&self) -> Vec<FieldLayout>;
+}
+
+/// Serialized size of a type, if it's always the same. Implemented by
+/// every non-generic `#[packet]` struct and all types used in their fields.
+pub trait FixedSize {
+    const FIXED_SIZE: Option<usize>;
+}
+
+macro_rules! impl_fixed_size_primitive {
+    ($($ty:ty),*) => {
+        $(impl FixedSize for $ty {
+            const FIXED_SIZE: Option<usize> = Some(std::mem::size_of::<$ty>());
+        })*
+    };
+}
+impl_fixed_size_primitive!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl<T: FixedSize, const N: usize> FixedSize for [T; N] {
+    const FIXED_SIZE: Option<usize> = match T::FIXED_SIZE {
+        Some(size) => Some(size * N),
+        None => None,
+    };
+}
+
+impl FieldLayout {
+    /// Append the layout of the next field. Used by `#[packet]`.
+    #[doc(hidden)]
+    pub fn push<T: bincode::Encode>(layout: &mut Vec<Self>, name: &'static str, field: &T) {
+        let offset = layout.last().map(|l| l.offset + l.len).unwrap_or(0);
+        // if this fails then at least count the bytes that could be encoded
+        let len = encoded_len(field);
+        layout.push(Self { name, offset, len });
+    }
+
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.offset..(self.offset + self.len)
+    }
+}
+
+/// Number of bytes the value serializes to. Used by `#[packet]` for
+/// `#[size_of_rest]` fields. Encoding errors are not reported here, they
+/// will surface when the value is actually serialized.
+#[doc(hidden)]
+pub fn encoded_len<T: bincode::Encode>(val: &T) -> usize {
+    let mut writer = SizeWriter::default();
+    let _ = bincode::encode_into_writer(val, &mut writer, bincode::config::legacy());
+    writer.bytes_written
+}
+
+/// Convert a computed length to the type of the field it's stored in.
+/// Used by `#[packet]`.
+#[doc(hidden)]
+pub fn computed_len<T: TryFrom<usize>>(field: &'static str, len: usize) -> Result<T, EncodeError> {
+    T::try_from(len)
+        .map_err(|_| EncodeError::OtherString(format!("{field}: {len} doesn't fit in the field")))
+}
+
+/// Validate a deserialized length field. Used by `#[packet]`.
+#[doc(hidden)]
+pub fn check_len<T: TryInto<usize> + Copy + Debug>(
+    field: &'static str,
+    found: T,
+    expected: usize,
+) -> Result<(), DecodeError> {
+    match found.try_into() {
+        Ok(found) if found == expected => Ok(()),
+        _ => Err(DecodeError::OtherString(format!(
+            "{field}: expected {expected}, found {found:?}"
+        ))),
+    }
+}
+
+/// Validate a deserialized `#[constant]` field. Used by `#[packet]`.
+#[doc(hidden)]
+pub fn check_constant<T: PartialEq + Debug>(
+    field: &'static str,
+    found: &T,
+    expected: &T,
+) -> Result<(), DecodeError> {
+    if found != expected {
+        return Err(DecodeError::OtherString(format!(
+            "{field}: expected {expected:?}, found {found:?}"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pkt_global::{DailyQuestResetTime, RouteHeader, SystemMessage};
+    use crate::pkt_login::S2CUrlList;
+    use crate::{BoundVec, Payload};
+    use packet_proc::packet;
+
+    #[packet(0x1)]
+    struct Computed {
+        #[size_of_rest]
+        num_bytes: u16,
+        #[constant = [0xfe, 0xa0]]
+        magic: [u8; 2],
+        #[len_of(items)]
+        num_items: u8,
+        items: BoundVec<0, u16>,
+        trailing: u8,
+    }
+
+    #[test]
+    fn test_layout() {
+        let p = SystemMessage {
+            aux: vec![1, 2, 3].into(),
+            ..Default::default()
+        };
+        let layout = p.layout();
+        let names: Vec<_> = layout.iter().map(|f| f.name).collect();
+        assert_eq!(
+            names,
+            [
+                "route_hdr",
+                "unk0",
+                "unk1",
+                "unk2",
+                "msg_type",
+                "aux_len",
+                "aux",
+                "trailing"
+            ]
+        );
+        let aux = &layout[6];
+        assert_eq!(aux.range(), 18..21);
+        assert_eq!(layout[7].offset, 21);
+    }
+
+    #[test]
+    fn test_computed_fields() {
+        let p = Computed {
+            items: vec![0x1234, 0x5678].into(),
+            trailing: 0xaa,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        p.serialize_no_hdr(&mut buf).unwrap();
+        assert_eq!(buf, [8, 0, 0xfe, 0xa0, 2, 0x34, 0x12, 0x78, 0x56, 0xaa]);
+
+        let p2 = Computed::deserialize_no_hdr(&buf).unwrap();
+        assert_eq!(p2.num_bytes, 8);
+        assert_eq!(p2.magic, [0xfe, 0xa0]);
+        assert_eq!(p2.num_items, 2);
+        assert_eq!(p2.items, p.items);
+        assert_eq!(p2.trailing, 0xaa);
+
+        let mut wrong = buf.clone();
+        wrong[0] = 7;
+        assert!(Computed::deserialize_no_hdr(&wrong).is_err());
+        let mut wrong = buf.clone();
+        wrong[3] = 0;
+        assert!(Computed::deserialize_no_hdr(&wrong).is_err());
+        // one item less leaves 2 bytes unparsed
+        let mut wrong = buf.clone();
+        wrong[4] = 1;
+        assert!(Computed::deserialize_no_hdr(&wrong).is_err());
+
+        let p = Computed {
+            items: vec![0; 256].into(),
+            ..Default::default()
+        };
+        assert!(p.serialize_no_hdr(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_fixed_size() {
+        assert_eq!(RouteHeader::FIXED_SIZE, Some(6));
+        assert_eq!(<[RouteHeader; 2]>::FIXED_SIZE, Some(12));
+        assert_eq!(DailyQuestResetTime::SIZE, Some(8));
+        // the size of the default packet is known, but `aux` can grow
+        assert_eq!(SystemMessage::FIXED_SIZE, None);
+        assert_eq!(SystemMessage::SIZE, None);
+        assert_eq!(Computed::FIXED_SIZE, None);
+    }
+
+    #[test]
+    fn test_size_of_rest_from() {
+        let mut p = S2CUrlList::default();
+        p.urls.push("http://localhost".into());
+        p.urls.push("".into());
+        let mut buf = Vec::new();
+        let len = p.serialize_no_hdr(&mut buf).unwrap();
+        assert_eq!(len, 4 + 4 + 16 + 4);
+        assert_eq!(buf[0..4], [(len - 2) as u8, 0, len as u8, 0]);
+
+        let p2 = S2CUrlList::deserialize_no_hdr(&buf).unwrap();
+        assert_eq!(p2.urls, p.urls);
+    }
+}
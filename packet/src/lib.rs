@@ -1,7 +1,7 @@
 // SPDX-License-Identifier: MIT
 // Copyright(c) 2023 Darek Stojaczyk
 
-// Rust-analyzer complains at the assert_def_packet_size! definition
+// #[packet(size = ...)] generates test modules in between the packets
 #![allow(clippy::items_after_test_module)]
 
 pub mod pkt_common;
@@ -18,3 +18,8 @@ pub use types::*;
 
 mod helper_types;
 pub use helper_types::*;
+mod layout;
+pub use layout::*;
+#[cfg(any(test, feature = "arbitrary"))]
+mod arbitrary_impls;
+mod serde_impls;
@@ -20,6 +20,12 @@ pub enum Packet {
     Connect,
     #[attr(path = crate::pkt_common::ConnectAck)]
     ConnectAck,
+    #[attr(path = crate::pkt_crypto::ConnectAck, from = RockNRoll)]
+    CryptoConnectAck,
+    #[attr(path = crate::pkt_event::ConnectAck, from = EventMgr)]
+    EventConnectAck,
+    #[attr(path = crate::pkt_party::ConnectAck, from = Party)]
+    PartyConnectAck,
 
     // Event Manager
     Keepalive,
@@ -30,6 +36,10 @@ pub enum Packet {
     KeyAuthRequest,
     KeyAuthResponse,
     ESYM,
+    #[attr(to = RockNRoll)]
+    ESYMRequest,
+    #[attr(from = RockNRoll)]
+    ESYMResponse,
 
     // Global Manager
     RegisterChatSvr,
@@ -42,6 +52,10 @@ pub enum Packet {
     SystemMessageForwarded,
     NotifyUserCount,
     ServerState,
+    #[attr(from = GlobalMgrSvr, to = LoginSvr)]
+    LoginServerState,
+    #[attr(from = GlobalMgrSvr, to = WorldSvr)]
+    WorldServerState,
     ProfilePathRequest,
     ProfilePathResponse,
     RoutePacket,
@@ -63,6 +77,20 @@ pub enum Packet {
     C2SAuthAccount,
     C2SVerifyLinks,
     C2SForceLogin,
+    #[attr(from = LoginSvr)]
+    S2CConnect,
+    #[attr(from = LoginSvr)]
+    S2CCheckVersion,
+    #[attr(from = LoginSvr)]
+    S2CEnvironment,
+    #[attr(from = LoginSvr)]
+    S2CRsaPubKey,
+    #[attr(from = LoginSvr)]
+    S2CAuthAccount,
+    #[attr(from = LoginSvr)]
+    S2CVerifyLinks,
+    #[attr(from = LoginSvr)]
+    S2CForceLogin,
 
     // Login Manager
     RequestClientVersion,
@@ -70,6 +98,10 @@ pub enum Packet {
 
     // Party Manager
     ClientConnect,
+    #[attr(to = Party)]
+    ClientConnectReq,
+    #[attr(from = Party)]
+    ClientConnectResp,
     PartyInvite,
     PartyInviteAck,
     PartyInviteResult,
@@ -143,3 +175,74 @@ impl Packet {
         Ok(Self::deserialize_no_hdr(hdr.id, pktbuf)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pkt_common::ServiceID;
+    use crate::{PacketContext, PayloadSerializeError};
+
+    #[test]
+    fn deserialize_ctx() {
+        let data = [0u8, 0x1, 0x2];
+        let p = Packet::deserialize_no_hdr(ServerState::ID, &data).unwrap();
+        assert!(matches!(p, Packet::ServerState(_)));
+
+        let ctx = PacketContext::new(Some(ServiceID::GlobalMgrSvr), Some(ServiceID::LoginSvr));
+        let p = Packet::deserialize_no_hdr_ctx(&ctx, ServerState::ID, &data).unwrap();
+        let Packet::LoginServerState(p) = p else {
+            panic!("Expected LoginServerState, got {p:?}");
+        };
+        assert_eq!(p.trailing.len(), 2);
+
+        // an unrelated context falls back to the generic packet
+        let ctx = PacketContext::new(Some(ServiceID::Party), None);
+        let p = Packet::deserialize_no_hdr_ctx(&ctx, ServerState::ID, &data).unwrap();
+        assert!(matches!(p, Packet::ServerState(_)));
+    }
+
+    #[test]
+    fn roundtrip_all_types() {
+        // xorshift, so the test is deterministic
+        let mut state = 0x2545f491u32;
+        let mut random_bytes = |len: usize| {
+            (0..len)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as u8
+                })
+                .collect::<Vec<u8>>()
+        };
+
+        for idx in 0..Packet::NUM_TYPES {
+            for i in 0..64 {
+                let bytes = random_bytes([0, 16, 64, 256, 1024, 4096][i % 6]);
+                let mut u = arbitrary::Unstructured::new(&bytes);
+                let p = Packet::arbitrary_of_type(idx, &mut u).unwrap();
+
+                for checksum in [true, false] {
+                    let mut buf = Vec::new();
+                    let len = match p.serialize(&mut buf, checksum) {
+                        Ok(len) => len,
+                        // too big to fit in a single packet
+                        Err(PayloadSerializeError::PayloadTooLong { .. }) => continue,
+                        Err(e) => panic!("Can't serialize {p:?}: {e}"),
+                    };
+                    assert_eq!(len, buf.len());
+
+                    let hdr = Header::deserialize(&buf, checksum).unwrap();
+                    assert_eq!(hdr.len as usize, len);
+                    let payload = &buf[Header::num_bytes(checksum)..];
+                    if let Some(size) = Packet::payload_size(&p.context(), hdr.id) {
+                        assert_eq!(payload.len(), size, "Unexpected size of {p:?}");
+                    }
+                    let p2 = Packet::deserialize_no_hdr_ctx(&p.context(), hdr.id, payload)
+                        .unwrap_or_else(|e| panic!("Can't deserialize {p:?}: {e}"));
+                    assert_eq!(p, p2);
+                }
+            }
+        }
+    }
+}
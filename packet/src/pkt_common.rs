@@ -6,7 +6,16 @@ use packet_proc::{packet, PacketEnum};
 
 use crate::BoundVec;
 
-#[derive(std::fmt::Debug, PartialEq, Clone, Default, bincode::Encode, bincode::Decode)]
+#[derive(
+    std::fmt::Debug,
+    PartialEq,
+    Clone,
+    Default,
+    bincode::Encode,
+    bincode::Decode,
+    serde::Serialize,
+    serde::Deserialize,
+)]
 pub struct Unknown {
     pub id: u16,
     pub data: UnknownPayload,
@@ -37,7 +46,18 @@ impl std::fmt::Display for Connect {
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, TryFromPrimitive, IntoPrimitive, PacketEnum)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    TryFromPrimitive,
+    IntoPrimitive,
+    PacketEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
 #[repr(u8)]
 pub enum ServiceID {
     #[default]
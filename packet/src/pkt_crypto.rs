@@ -1,10 +1,10 @@
 // SPDX-License-Identifier: MIT
 // Copyright(c) 2023 Darek Stojaczyk
 
-use crate::{assert_def_packet_size, BoundVec};
+use crate::{Block, BoundVec, NulltermString};
 use packet_proc::packet;
 
-#[packet(0x6)]
+#[packet(0x6, size = 19)]
 pub struct ConnectAck {
     unk1: u32,     // 0x0?
     unk2: [u8; 8], // hardcoded to [0x00, 0xff, 0x00, 0xff, 0xf5, 0x00, 0x00, 0x00]
@@ -13,24 +13,21 @@ pub struct ConnectAck {
     unk5: u32, // hardcoded to 0x0
     unk6: u8,  // hardcoded to 0x1
 }
-assert_def_packet_size!(ConnectAck, 19);
 
-#[packet(0x305)]
+#[packet(0x305, size = 14 - Header::SIZE)]
 pub struct EncryptKey2Request {
     key_split_point: u32, // xored with 0x1f398ab3. usually ends up either 1 or 5
                           // the short key should be split at this index into two parts,
                           // then constructed from those parts in reverse order
 }
-assert_def_packet_size!(EncryptKey2Request, 14 - Header::SIZE);
 
-#[packet(0x306)]
+#[packet(0x306, size = 14 - Header::SIZE)]
 pub struct EncryptKey2Response {
     key_split_point: u32, // un-xored, usually either 1 or 5
     shortkey: BoundVec<0, u8>,
 }
-assert_def_packet_size!(EncryptKey2Response, 14 - Header::SIZE);
 
-#[packet(0x2f3)]
+#[packet(0x2f3, size = 182 - Header::SIZE)]
 pub struct KeyAuthRequest {
     unk1: u32,           // 0x0
     unk2: u32,           // 0x0
@@ -40,9 +37,8 @@ pub struct KeyAuthRequest {
     binbuf: [Block; 4],  // expecting "empty"
     xor_port: u32,       // global db agent port? xored with 0x1f398ab3, ends up 38180
 }
-assert_def_packet_size!(KeyAuthRequest, 182 - Header::SIZE);
 
-#[packet(0x2f4)]
+#[packet(0x2f4, size = 809 - Header::SIZE)]
 pub struct KeyAuthResponse {
     unk1: u32,             // 0x1
     xor_unk2: u32,         // xored with 0x1f398ab3, usually ends up 0x03010101
@@ -55,7 +51,6 @@ pub struct KeyAuthResponse {
     enc_warp: [Block; 16], // ^
     port: u32,             // global db agent port? 38180
 }
-assert_def_packet_size!(KeyAuthResponse, 809 - Header::SIZE);
 
 // The same packet ID used by request and response.
 // Possibly an oversight in original design
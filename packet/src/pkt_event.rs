@@ -1,10 +1,10 @@
 // SPDX-License-Identifier: MIT
 // Copyright(c) 2023 Darek Stojaczyk
 
-use crate::assert_def_packet_size;
-use packet_proc::packet;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use packet_proc::{packet, PacketEnum};
 
-#[packet(0x6)]
+#[packet(0x6, size = 20)]
 pub struct ConnectAck {
     unk1: u32,     // 0x0?
     unk2: [u8; 9], // hardcoded to [0x00, 0xff, 0x00, 0xff, 0xf5, 0x00, 0x00, 0x00, 0x00]
@@ -13,8 +13,45 @@ pub struct ConnectAck {
     unk3: u32, // hardcoded to 0x0
     unk4: u8,  // hardcoded to 0x1
 }
-assert_def_packet_size!(ConnectAck, 20);
 
-#[packet(0x2b3)]
+#[packet(0x2b3, size = 0)]
 pub struct Keepalive {}
-assert_def_packet_size!(Keepalive, 0);
+
+// The event packets below weren't observed in any capture of the original
+// EventMgr yet, so their layout is only a guess. They're not in the Packet
+// list either, so the real packets with the same IDs are received as Unknown
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    TryFromPrimitive,
+    IntoPrimitive,
+    PacketEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[repr(u32)]
+pub enum EventType {
+    #[default]
+    Exp = 0x1,
+    SkillExp = 0x2,
+    Drop = 0x3,
+    AlzDrop = 0x4,
+}
+
+#[packet(0x2b4, size = 20)]
+pub struct EventStart {
+    event_id: u32,
+    event_type: EventType,
+    rate: u32,       // in percent, 100 = unchanged
+    start_time: u32, // unix timestamp
+    end_time: u32,   // unix timestamp
+}
+
+#[packet(0x2b5, size = 4)]
+pub struct EventStop {
+    event_id: u32,
+}
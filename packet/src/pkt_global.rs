@@ -4,9 +4,9 @@
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use packet_proc::{packet, PacketEnum};
 
-use crate::{assert_def_packet_size, BoundVec};
+use crate::{Arr, BoundVec, Payload, PayloadDeserializeError, PayloadSerializeError};
 
-#[packet(0x50)]
+#[packet(0x50, size = 6)]
 pub struct RegisterChatSvr {
     server_id: u8,  // 1?
     channel_id: u8, // 1?
@@ -14,9 +14,19 @@ pub struct RegisterChatSvr {
     unk1: u8,       // 0?
     port: u16,      // e.g. 0x94e9 = 38121
 }
-assert_def_packet_size!(RegisterChatSvr, 6);
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, TryFromPrimitive, IntoPrimitive, PacketEnum)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    TryFromPrimitive,
+    IntoPrimitive,
+    PacketEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
 #[repr(u32)]
 pub enum ServerStateEnum {
     #[default]
@@ -26,13 +36,12 @@ pub enum ServerStateEnum {
     Red = 0x3,    // population full
 }
 
-#[packet(0x62)]
+#[packet(0x62, size = 6)]
 pub struct ChangeServerState {
     server_id: u8,
     channel_id: u8,
     state: ServerStateEnum,
 }
-assert_def_packet_size!(ChangeServerState, 6);
 
 #[packet(0x63)]
 pub struct ChangeChannelType {
@@ -41,26 +50,23 @@ pub struct ChangeChannelType {
     state: u32,
 }
 
-#[packet(0xc3e)]
+#[packet(0xc3e, size = 8)]
 pub struct ClientVersionNotify {
     version: u32,  // can be zero (-> ignore?)
     magickey: u32, // ^
 }
-assert_def_packet_size!(ClientVersionNotify, 8);
 
-#[packet(0xbd5)]
+#[packet(0xbd5, size = 8)]
 pub struct DailyQuestResetTime {
     next_daily_reset_time: u32, // unix timestamp, unknown timezone
     unk2: u32,                  // usually 0
 }
-assert_def_packet_size!(DailyQuestResetTime, 8);
 
-#[packet(0xbec)]
+#[packet(0xbec, size = 8)]
 pub struct AdditionalDungeonInstanceCount {
     unk1: u32, // 1/0? usually 0
     unk2: u32, // actual count?
 }
-assert_def_packet_size!(AdditionalDungeonInstanceCount, 8);
 
 #[packet]
 pub struct RouteHeader {
@@ -79,18 +85,19 @@ pub struct RouteHeader {
 
 */
 
-#[packet(0x15)]
+#[packet(0x15, size = 0x1d - 1 - Header::SIZE)]
 pub struct SystemMessage {
     route_hdr: RouteHeader,
     unk0: u32,    // 0?
     unk1: u16,    // 0?
     unk2: u32,    // 1? user id
     msg_type: u8, // 0,1,2,3? or 9...
-    aux: BoundVec<1, u8>,
+    #[len_of(aux)]
+    aux_len: u8,
+    aux: BoundVec<0, u8>,
     // there might be 1 trailing byte in case aux is empty
     trailing: BoundVec<0, u8>,
 }
-assert_def_packet_size!(SystemMessage, 0x1d - 1 - Header::SIZE);
 /*
 
 
@@ -106,7 +113,7 @@ req:
 > @annotate [8-10] unk1
 > @annotate [10-14] unk2
 > @annotate [14-15] msg_type
-> @annotate [15-16] aux len (0)
+> @annotate [15-16] aux_len (0)
 0060   00                                                .
 > @annotate [0-1] a trailing byte
 
@@ -122,7 +129,7 @@ pub struct SystemMessageForwarded {
     data: SystemMessage,
 }
 
-#[packet(0x34)]
+#[packet(0x34, size = 0x1b4 - Header::SIZE)]
 pub struct NotifyUserCount {
     server_id: u8,
     channel_id: u8,
@@ -132,7 +139,6 @@ pub struct NotifyUserCount {
     user_count: Arr<u16, 200>,
     unk: [u8; 18],
 }
-assert_def_packet_size!(NotifyUserCount, 0x1b4 - Header::SIZE);
 // [2024-03-03T13:23:57.930Z TRACE server::proxy] Conn #9: Got up packet(0x34): Unknown(Unknown { id: 52, data: BoundVec( [128, 1,  0, 0, 0,   0,   0,   0, 0, 0, 1) })
 // [2024-03-03T13:23:54.118Z TRACE server::proxy] Conn #15: Got up packet(0x34): Unknown(Unknown { id: 52, data: BoundVec([1,   2, 10, 2, 0, 143, 224, 148, 0, 0, 0) })
 // [2024-03-03T13:23:52.074Z TRACE server::proxy] Conn #13: Got up packet(0x34): Unknown(Unknown { id: 52, data: BoundVec([1,   1, 10, 2, 0, 143, 223, 148, 0, 0, 1) })
@@ -154,17 +160,16 @@ pub struct WorldServerState {
     groups: BoundVec<1, GroupNode>,
 }
 
-#[packet]
+#[packet(size = 0x7)]
 pub struct LoginServerNode {
     id: u8,    // 1 - server id?
     stype: u8, // usually 0x10 (set in globalmgrsvr ini)
     unk1: u32, // 0
     groups: BoundVec<1, GroupNode>,
 }
-assert_def_packet_size!(LoginServerNode, 0x7);
 
 // must be 0x25 bytes
-#[packet]
+#[packet(size = 37)]
 pub struct GroupNode {
     id: u8,
     unk0: u16,
@@ -180,7 +185,6 @@ pub struct GroupNode {
     port: u16,        // 0x94df
     state: u32,       // 0x5
 }
-assert_def_packet_size!(GroupNode, 37);
 /*
 0000   01 01 00 00 00 00 00 00 00 00 00 00 00 00 00 00   ................
 > @annotate [0-1] unk1
@@ -207,7 +211,7 @@ pub struct ProfilePathRequest {
     unk1: u32, // hardcoded 0
 }
 
-#[packet(0x2f7)]
+#[packet(0x2f7, size = 0x311 - Header::SIZE)]
 pub struct ProfilePathResponse {
     unk1: u32, // either 0x5 or 0x6, but worldsvr doesn't seem to parse it, just checks != 0
 
@@ -218,7 +222,6 @@ pub struct ProfilePathResponse {
     scp_id3: u8,
     scp_path3: Arr<u8, 0x100>,
 }
-assert_def_packet_size!(ProfilePathResponse, 0x311 - Header::SIZE);
 
 #[packet]
 pub struct DuplexRouteHeader {
@@ -234,14 +237,13 @@ pub struct DuplexRouteHeader {
                          // but WorldSvr can send us a bugged 0x2b message with without it
 }
 
-#[packet(0x1a)]
+#[packet(0x1a, size = 17)]
 pub struct RoutePacket {
     droute_hdr: DuplexRouteHeader, // desired msg id non 0,
     // 0x17 - special handling, no server_id/group_id checked
     data: BoundVec<0, u8>, // the [`DuplexRouteHeader::resp_process_id`]
                            // might be contained inside
 }
-assert_def_packet_size!(RoutePacket, 17);
 
 /// Wrapper to serialize given Payload under a different packet ID
 #[packet]
@@ -282,7 +284,7 @@ pub struct ChannelOptionSync {
     unk4: u32, // 5
 }
 
-#[packet(0x17)]
+#[packet(0x17, size = 424)]
 pub struct VerifyLinks {
     droute_hdr: DuplexRouteHeader,
     resp_process_id: u8,
@@ -306,16 +308,14 @@ pub struct VerifyLinks {
     unk12: Arr<u8, 285>,
     username: Arr<u8, 33>,
 }
-assert_def_packet_size!(VerifyLinks, 424);
 
-#[packet(0x18)]
+#[packet(0x18, size = 0x21 - Header::SIZE)]
 pub struct VerifyLinksResult {
     droute_hdr: DuplexRouteHeader,
     resp_process_id: u8,
     user_idx: u32, // 8?
     status: u8,    // 226?
 }
-assert_def_packet_size!(VerifyLinksResult, 0x21 - Header::SIZE);
 
 #[packet(0x2dc)]
 pub struct SubPasswordCheckRequest {
@@ -327,7 +327,7 @@ pub struct SubPasswordCheckRequest {
     unk4: u32,          // 0x0?
 }
 
-#[packet(0x2dd)]
+#[packet(0x2dd, size = 0x2f - Header::SIZE)]
 pub struct SubPasswordCheckResponse {
     unk1: u32,          // 1?
     auth_needed: u32,   // 0 or 1
@@ -337,7 +337,6 @@ pub struct SubPasswordCheckResponse {
     login_counter: u32, // 0x8? 0x9? 0x10? from req
     unk4: u32,          // 0x0? from req
 }
-assert_def_packet_size!(SubPasswordCheckResponse, 0x2f - Header::SIZE);
 
 #[packet(0xc7c)]
 pub struct MultipleLoginDisconnectRequest {
@@ -352,7 +351,7 @@ pub struct MultipleLoginDisconnectResponse {
     login_idx: u32,
 }
 
-#[packet(0x1c)]
+#[packet(0x1c, size = 0x14b - Header::SIZE)]
 pub struct SetLoginInstance {
     user_id: u32,
     login_idx: u32,
@@ -364,7 +363,6 @@ pub struct SetLoginInstance {
     unk8: [u8; 9],       // hardcoded zeroes
     unk9: Arr<u8, 0xf0>, // zeroes? uninitialized
 }
-assert_def_packet_size!(SetLoginInstance, 0x14b - Header::SIZE);
 
 /*
 0000   01 00 00 00 3c 01 00 00 00 61 64 6d 69 6e 00 00   ....<....admin..
@@ -440,6 +438,7 @@ assert_def_packet_size!(SetLoginInstance, 0x14b - Header::SIZE);
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Packet;
     use bincode::config;
 
     #[test]
@@ -552,8 +551,8 @@ mod tests {
             0x69, 0x6e, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
             0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
         ];
-        let data = Packet::deserialize(&buf, true).unwrap();
-        //println!("{:?}", data);
+        let _data = Packet::deserialize(&buf, true).unwrap();
+        //println!("{:?}", _data);
 
         buf[8] = 0x17;
         let data = Packet::deserialize(&buf, true).unwrap();
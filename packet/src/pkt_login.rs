@@ -1,62 +1,55 @@
 // SPDX-License-Identifier: MIT
 // Copyright(c) 2024 Darek Stojaczyk
 
+use crate::pkt_global::LoginServerNode;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use packet_proc::{packet, PacketEnum};
-use pkt_global::LoginServerNode;
 
-use crate::{assert_def_packet_size, BoundVec};
+use crate::{Arr, BoundVec};
 
-#[packet(0x65)]
+#[packet(0x65, size = 4)]
 pub struct C2SConnect {
     auth_key: u32,
 }
-assert_def_packet_size!(C2SConnect, 4);
 
-#[packet(0x65)]
+#[packet(0x65, size = 12)]
 pub struct S2CConnect {
     xor_seed_2: u32,
     auth_key: u32,
     user_idx: u16,
     xor_key_idx: u16,
 }
-assert_def_packet_size!(S2CConnect, 12);
 
-#[packet(0x7a)]
+#[packet(0x7a, size = 16)]
 pub struct C2SCheckVersion {
     client_version: u32,
     unk1: u32, // 0
     unk2: u32, // 0
     unk3: u32, // 0
 }
-assert_def_packet_size!(C2SCheckVersion, 16);
 
-#[packet(0x7a)]
+#[packet(0x7a, size = 16)]
 pub struct S2CCheckVersion {
     server_version: u32,
     server_magic_key: u32,
     unk2: u32, // 0
     unk3: u32, // 0
 }
-assert_def_packet_size!(S2CCheckVersion, 16);
 
-#[packet(0x7d2)]
+#[packet(0x7d2, size = 33)]
 pub struct C2SEnvironment {
     username: Arr<u8, 33>,
 }
-assert_def_packet_size!(C2SEnvironment, 33);
 
-#[packet(0x7d2)]
+#[packet(0x7d2, size = 4113)]
 pub struct S2CEnvironment {
     unk1: Arr<u8, 0x100e>, // zeroes, related to image auth
     unk2: u16,             // 0x14c8? junk?
     unk3: u8,              // 0?
 }
-assert_def_packet_size!(S2CEnvironment, 4113);
 
-#[packet(0x7d1)]
+#[packet(0x7d1, size = 0)]
 pub struct C2SRequestRsaPubKey;
-assert_def_packet_size!(C2SRequestRsaPubKey, 0);
 
 #[packet(0x7d1)]
 pub struct S2CRsaPubKey {
@@ -65,15 +58,14 @@ pub struct S2CRsaPubKey {
     pub_key: BoundVec<0, u8>,
 }
 
-#[packet(0x67)]
+#[packet(0x67, size = 258)]
 pub struct C2SAuthAccount {
     unk1: u8, // 0?
     unk2: u8, // 1?
     encoded_pass: Arr<u8, 256>,
 }
-assert_def_packet_size!(C2SAuthAccount, 258);
 
-#[packet(0x67)]
+#[packet(0x67, size = 70)]
 pub struct S2CAuthAccount {
     status: u8,   // 0x20
     user_id: u32, // ?? 1
@@ -89,7 +81,6 @@ pub struct S2CAuthAccount {
     unkkey: Arr<u8, 33>,         // string? always null terminated
     characters: BoundVec<0, u8>, // u8 pairs of (server_id, char_id) on successfull login, or [0]
 }
-assert_def_packet_size!(S2CAuthAccount, 70);
 
 #[packet(0x79)]
 pub struct S2CServerList {
@@ -98,7 +89,9 @@ pub struct S2CServerList {
 
 #[packet(0x80)]
 pub struct S2CUrlList {
+    #[size_of_rest]
     urls_num_bytes: u16,
+    #[size_of_rest(from = urls_num_bytes)]
     urls_num_bytes2: u16,
     urls: BoundVec<0, BoundVec<4, u8>>,
 }
@@ -110,7 +103,18 @@ pub struct S2CSystemMessage {
     data2: u8,
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, TryFromPrimitive, IntoPrimitive, PacketEnum)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    TryFromPrimitive,
+    IntoPrimitive,
+    PacketEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
 #[repr(u8)]
 pub enum SystemMessageType {
     #[default]
@@ -121,7 +125,7 @@ pub enum SystemMessageType {
     Login = 0x9,
 }
 
-#[packet(0x66)]
+#[packet(0x66, size = 12)]
 pub struct C2SVerifyLinks {
     unk1: u32, // some key, 0x4350
     unique_idx: u16,
@@ -129,7 +133,6 @@ pub struct C2SVerifyLinks {
     server_id: u8,
     magic_key: u32,
 }
-assert_def_packet_size!(C2SVerifyLinks, 12);
 
 #[packet(0x66)]
 pub struct S2CVerifyLinks {
@@ -139,7 +142,7 @@ pub struct S2CVerifyLinks {
 #[packet(0xc3d)]
 pub struct RequestClientVersion;
 
-#[packet(0x1e)]
+#[packet(0x1e, size = 139)]
 pub struct RequestAuthAccount {
     server_id: u8,  // 0x80?
     channel_id: u8, // 1?
@@ -149,9 +152,8 @@ pub struct RequestAuthAccount {
     password: Arr<u8, 97>,
     zero: u8,
 }
-assert_def_packet_size!(RequestAuthAccount, 139);
 
-#[packet(0x1f)]
+#[packet(0x1f, size = 189)]
 pub struct ResponseAuthAccount {
     server_id: u8,    // 0x80?
     channel_id: u8,   // 1?
@@ -205,7 +207,6 @@ pub struct ResponseAuthAccount {
     login_idx: u32,              // total login count
     characters: BoundVec<0, u8>, // i.e. [1, 3] on successful login (server idx1, character idx3), or [0]
 }
-assert_def_packet_size!(ResponseAuthAccount, 189);
 
 /*
 > @annotate-cfg [clamp = [7, 54]]
@@ -292,6 +293,7 @@ assert_def_packet_size!(ResponseAuthAccount, 189);
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Payload;
 
     #[test]
     fn test_decode_2() {
@@ -308,12 +310,11 @@ mod tests {
     }
 }
 
-#[packet(0x6d)]
+#[packet(0x6d, size = 5)]
 pub struct C2SForceLogin {
     do_disconnect: u32, // 0 or 1
     unk1: u8,           // 1 ?? not processed by loginsvr
 }
-assert_def_packet_size!(C2SForceLogin, 5);
 
 #[packet(0x6d)]
 pub struct S2CForceLogin {
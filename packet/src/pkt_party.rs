@@ -1,10 +1,11 @@
 // SPDX-License-Identifier: MIT
 // Copyright(c) 2023 Darek Stojaczyk
 
-use crate::assert_def_packet_size;
 use packet_proc::packet;
 
-#[packet(0x6)]
+use crate::BoundVec;
+
+#[packet(0x6, size = 20)]
 pub struct ConnectAck {
     unk1: [u8; 8],  // hardcoded to [0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x00, 0xff]
     service_id: u8, // 0xf7 - partysvr
@@ -14,16 +15,14 @@ pub struct ConnectAck {
     unk3: u32, // 0
     unk4: u8,  // 1
 }
-assert_def_packet_size!(ConnectAck, 20);
 
 #[packet(0xbce)]
 pub struct ClientConnect {
     bytes: BoundVec<0, u8>,
 }
 // ^ response: first u32, then zeroes for a total pkt len 411
-//assert_def_packet_size!(ClientConnect, 31);
 
-#[packet(0xbce)]
+#[packet(0xbce, size = 31)]
 pub struct ClientConnectReq {
     char_id: u32,    // 0x18?
     channel_id: u32, // ?? 1
@@ -39,10 +38,9 @@ pub struct ClientConnectResp {
     char_id: u32,
     has_party: u32,
     party_stats: PartyStats, // tgt_char_id == 0
-    bytes: BoundVec<0, u8>,
 }
 
-#[packet(0xbbb)]
+#[packet(0xbbb, size = 55)]
 pub struct PartyInvite {
     unk1: u8, // 1
     invitee_name_len: u8,
@@ -58,10 +56,9 @@ pub struct PartyInvite {
     inviter_name_len: u8,
     inviter_name: [u8; 16],
 }
-assert_def_packet_size!(PartyInvite, 55);
 // ^ response: same pkt
 
-#[packet(0xbbc)]
+#[packet(0xbbc, size = 33)]
 pub struct PartyInviteAck {
     inviter_id: u32,        // 8
     inviter_channel_id: u8, // 1
@@ -73,10 +70,9 @@ pub struct PartyInviteAck {
     invitee_name: [u8; 16],
     unk7: u8,
 }
-assert_def_packet_size!(PartyInviteAck, 33);
 // ^ response: same pkt
 
-#[packet(0xbbd)]
+#[packet(0xbbd, size = 37)]
 pub struct PartyInviteResult {
     inviter_id: u32,
     inviter_channel_id: u8, // 1
@@ -89,7 +85,6 @@ pub struct PartyInviteResult {
     invitee_name_len: u8,
     invitee_name: [u8; 16],
 }
-assert_def_packet_size!(PartyInviteResult, 37);
 // ^ response: 0xbbe + same pkt (+ 2x 0xbbf ?)
 
 #[packet(0xbbe)]
@@ -108,7 +103,7 @@ pub struct PartyStats {
     unk4: u8,      // 1
     unk5: u8,      // 1
     chars: BoundVec<4, PartyCharacterStat>,
-    padding: BoundVec<0, u8>,
+    padding: BoundVec<0, u8>, // any trailing bytes, also in ClientConnectResp
 }
 
 #[packet]
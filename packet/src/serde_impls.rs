@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+//! Serde support for the helper types.
+//!
+//! Raw bytes are represented as hex strings. Fixed-size byte arrays that
+//! usually hold text ([`Arr<u8, N>`]) are represented as plain strings
+//! whenever possible, and as `0x`-prefixed hex strings otherwise.
+
+use std::any::TypeId;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+use serde::de::{Error, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Arr, Block, BoundVec, NulltermString};
+
+/// Used by `#[packet]` for `[u8; N]` fields
+pub mod hex_array {
+    use super::*;
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        val: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(val))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let str = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        let mut ret = [0u8; N];
+        hex::decode_to_slice(&*str, &mut ret).map_err(D::Error::custom)?;
+        Ok(ret)
+    }
+}
+
+impl Serialize for Block {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        hex_array::serialize(self.as_ref(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Block {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        hex_array::deserialize(deserializer).map(Block::from)
+    }
+}
+
+impl<const S: usize, T: Serialize + 'static> Serialize for BoundVec<S, T> {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        if TypeId::of::<T>() == TypeId::of::<u8>() {
+            // Safety: T = u8
+            let bytes: &[u8] = unsafe { core::mem::transmute(&self.0[..]) };
+            return serializer.serialize_str(&hex::encode(bytes));
+        }
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, const S: usize, T: Deserialize<'de> + 'static> Deserialize<'de> for BoundVec<S, T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if TypeId::of::<T>() == TypeId::of::<u8>() {
+            let str = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+            let bytes = hex::decode(&*str).map_err(D::Error::custom)?;
+            // Safety: Vec<T> is Vec<u8>
+            return Ok(BoundVec(unsafe {
+                core::mem::transmute::<Vec<u8>, Vec<T>>(bytes)
+            }));
+        }
+        Vec::<T>::deserialize(deserializer).map(BoundVec)
+    }
+}
+
+impl Serialize for NulltermString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for NulltermString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(NulltermString)
+    }
+}
+
+/// Get the text from a zero-padded byte array, if there's any
+fn bytes_as_text(bytes: &[u8]) -> Option<&str> {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    if bytes[len..].iter().any(|b| *b != 0) {
+        return None;
+    }
+    let str = std::str::from_utf8(&bytes[..len]).ok()?;
+    if str.starts_with("0x") || str.chars().any(|c| c.is_control()) {
+        return None;
+    }
+    Some(str)
+}
+
+impl<T: Serialize + Debug + 'static, const N: usize> Serialize for Arr<T, N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if TypeId::of::<T>() == TypeId::of::<u8>() {
+            // Safety: T = u8
+            let bytes: &[u8] = unsafe { core::mem::transmute(&self[..]) };
+            return match bytes_as_text(bytes) {
+                Some(str) => serializer.serialize_str(str),
+                None => serializer.serialize_str(&format!("0x{}", hex::encode(bytes))),
+            };
+        }
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Copy + Debug + 'static, const N: usize> Deserialize<'de>
+    for Arr<T, N>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if TypeId::of::<T>() == TypeId::of::<u8>() {
+            let str = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+            let bytes = match str.strip_prefix("0x") {
+                Some(hex) => hex::decode(hex).map_err(D::Error::custom)?,
+                None => str.as_bytes().to_vec(),
+            };
+            if bytes.len() > N {
+                return Err(D::Error::invalid_length(bytes.len(), &"at most N bytes"));
+            }
+            // Safety: T = u8
+            let bytes: &[T] = unsafe { core::mem::transmute(&bytes[..]) };
+            return Ok(Arr::from(bytes));
+        }
+
+        struct ArrVisitor<T, const N: usize>(PhantomData<T>);
+        impl<'de, T: Deserialize<'de> + Copy + Debug + 'static, const N: usize> Visitor<'de>
+            for ArrVisitor<T, N>
+        {
+            type Value = Arr<T, N>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "an array of {N} elements")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut ret = Arr::<T, N>::default();
+                for idx in 0..N {
+                    ret[idx] = seq
+                        .next_element()?
+                        .ok_or_else(|| A::Error::invalid_length(idx, &self))?;
+                }
+                if seq.next_element::<T>()?.is_some() {
+                    return Err(A::Error::invalid_length(N + 1, &self));
+                }
+                Ok(ret)
+            }
+        }
+        deserializer.deserialize_seq(ArrVisitor::<T, N>(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pkt_global::{NotifyUserCount, RouteHeader};
+    use crate::pkt_login::C2SEnvironment;
+
+    #[test]
+    fn test_json_roundtrip() {
+        let p = C2SEnvironment {
+            username: b"admin"[..].into(),
+        };
+        let json = serde_json::to_value(&p).unwrap();
+        assert_eq!(json["username"], "admin");
+        let p2: C2SEnvironment = serde_json::from_value(json).unwrap();
+        assert_eq!(p, p2);
+
+        let mut p = NotifyUserCount::default();
+        p.user_count[1] = 5;
+        let json = serde_json::to_string(&p).unwrap();
+        let p2: NotifyUserCount = serde_json::from_str(&json).unwrap();
+        assert_eq!(p, p2);
+    }
+
+    #[test]
+    fn test_json_bytes() {
+        let mut arr: Arr<u8, 4> = b"\x01ab"[..].into();
+        assert_eq!(serde_json::to_string(&arr).unwrap(), "\"0x01616200\"");
+        arr[0] = b'x';
+        assert_eq!(serde_json::to_string(&arr).unwrap(), "\"xab\"");
+        let arr2: Arr<u8, 4> = serde_json::from_str("\"xab\"").unwrap();
+        assert_eq!(arr, arr2);
+        assert!(serde_json::from_str::<Arr<u8, 2>>("\"xab\"").is_err());
+
+        let vec: BoundVec<0, u8> = vec![0xe2, 0xb7].into();
+        assert_eq!(serde_json::to_string(&vec).unwrap(), "\"e2b7\"");
+
+        let hdr = RouteHeader {
+            origin_main_cmd: 0x15,
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&hdr).unwrap();
+        assert_eq!(json["origin_main_cmd"], 0x15);
+    }
+}
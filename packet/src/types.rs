@@ -7,7 +7,7 @@ use bincode::{
 };
 use thiserror::Error;
 
-use crate::pkt_common::Unknown;
+use crate::pkt_common::{ServiceID, Unknown};
 
 #[derive(Debug, PartialEq)]
 pub struct Header {
@@ -23,6 +23,8 @@ impl Header {
     pub const MAGIC: u16 = 0xb7e2;
     pub const SIZE: usize = 10;
 
+    /// The checksum is always 0 here. For encoded packets, it's computed
+    /// only once the packet is encoded, as it depends on the encoded data.
     pub fn new(id: u16, len: u16, serialize_checksum: bool) -> Self {
         Self {
             len,
@@ -98,6 +100,30 @@ impl Header {
     }
 }
 
+/// Sender and receiver of a packet, if known. Some packet IDs are shared
+/// by multiple packet types, e.g. a request and its response, and only
+/// the context tells them apart.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PacketContext {
+    pub from: Option<ServiceID>,
+    pub to: Option<ServiceID>,
+}
+
+impl PacketContext {
+    pub fn new(from: Option<ServiceID>, to: Option<ServiceID>) -> Self {
+        Self { from, to }
+    }
+
+    /// Check if the context satisfies the given sender and receiver.
+    /// `None` matches any service.
+    pub fn matches(&self, from: Option<ServiceID>, to: Option<ServiceID>) -> bool {
+        let matches = |expected: Option<ServiceID>, actual: Option<ServiceID>| {
+            expected.is_none() || expected == actual
+        };
+        matches(from, self.from) && matches(to, self.to)
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum HeaderDeserializeError {
     #[error(
@@ -139,30 +165,12 @@ pub enum PacketDeserializeError {
     Payload(#[from] PayloadDeserializeError),
 }
 
-#[macro_export]
-macro_rules! assert_def_packet_size {
-    ($pkt:ident, $size:expr) => {
-        paste::paste! {
-            #[allow(unused_imports)]
-            use super::*;
-            #[cfg(test)]
-            #[allow(non_snake_case, clippy::items_after_test_module)]
-            mod [<$pkt _test_def_packet_size>] {
-                use super::*;
-                #[test]
-                fn test() {
-                    let mut buf = [0u8; 8192];
-                    let len = bincode::encode_into_slice($pkt::default(), &mut buf, bincode::config::legacy()).unwrap();
-                    assert_eq!(len, $size);
-                }
-            }
-        }
-    };
-}
-
 pub trait Payload:
     std::fmt::Debug + PartialEq + Clone + Default + bincode::Encode + bincode::Decode + 'static
 {
+    /// Serialized payload size, if it's always the same
+    const SIZE: Option<usize> = None;
+
     fn id(&self) -> u16;
 
     fn serialize(
@@ -196,10 +204,15 @@ pub trait Payload:
         Ok(len)
     }
 
-    fn deserialize_no_hdr(data: &[u8]) -> Result<Self, PayloadDeserializeError> {
+    fn deserialize_no_hdr(data: &[u8]) -> Result<Self, PayloadDeserializeError>
+    where
+        Self: for<'a> bincode::BorrowDecode<'a>,
+    {
         assert!(TypeId::of::<Self>() != TypeId::of::<Unknown>());
 
-        let (obj, len) = bincode::decode_from_slice::<Self, _>(data, config::legacy())?;
+        // no intermediate buffers, but the decoded fields still own
+        // their data, i.e. BoundVec-s and Strings are copied
+        let (obj, len) = bincode::borrow_decode_from_slice::<Self, _>(data, config::legacy())?;
         if len != data.len() {
             return Err(PayloadDeserializeError::PacketTooLong {
                 len: data.len() as u16,
@@ -10,6 +10,7 @@ use syn::{
 pub(crate) struct EnumInfo {
     pub(crate) name: Ident,
     pub(crate) repr: Ident,
+    pub(crate) variants: Vec<Ident>,
 }
 
 macro_rules! die {
@@ -62,8 +63,16 @@ impl Parse for EnumInfo {
             let name = input.ident;
 
             let repr = Self::parse_attrs(input.attrs.into_iter())?;
+            let variants = match input.data {
+                syn::Data::Enum(data) => data.variants.into_iter().map(|v| v.ident).collect(),
+                _ => die!(name => "Expected an enum"),
+            };
 
-            EnumInfo { name, repr }
+            EnumInfo {
+                name,
+                repr,
+                variants,
+            }
         })
     }
 }
@@ -0,0 +1,269 @@
+/* SPDX-License-Identifier: MIT
+ * Copyright(c) 2024 Darek Stojaczyk
+ */
+
+//! Computed fields in `#[packet]` structs.
+//!
+//! ```ignore
+//! #[packet(0x42)]
+//! pub struct Example {
+//!     #[size_of_rest]
+//!     num_bytes: u16, // bytes after this field
+//!     #[constant = 0x1]
+//!     version: u8,
+//!     #[len_of(items)]
+//!     num_items: u8,
+//!     items: BoundVec<0, u32>,
+//! }
+//! ```
+//!
+//! Values of those fields are computed when serializing (whatever is
+//! stored in the struct is ignored), and validated when deserializing.
+//! `#[len_of]` additionally decides how many items are deserialized into
+//! the target vector, so it can be followed by more fields.
+//!
+//! `#[size_of_rest(from = field)]` counts the bytes from the given field
+//! (inclusive) instead. The const attribute is spelled `#[constant = ..]`,
+//! as `const` is a keyword and can't be used as an attribute name.
+
+use proc_macro2::TokenStream;
+
+pub enum FieldAttr {
+    /// Number of items in the given vector
+    LenOf(syn::Ident),
+    /// Number of bytes after this field, or starting at `from`
+    SizeOfRest { from: Option<syn::Ident> },
+    /// Always the same value
+    Constant(syn::Expr),
+}
+
+struct SizeOfRestArgs {
+    from: syn::Ident,
+}
+
+impl syn::parse::Parse for SizeOfRestArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::parse::Result<Self> {
+        let name = input.parse::<syn::Ident>()?;
+        if name != "from" {
+            return Err(syn::parse::Error::new_spanned(name, "Unknown attribute"));
+        }
+        input.parse::<syn::Token![=]>()?;
+        Ok(Self {
+            from: input.parse()?,
+        })
+    }
+}
+
+impl FieldAttr {
+    fn parse(attr: &syn::Attribute) -> syn::Result<Option<Self>> {
+        let path = attr.path();
+        let ret = if path.is_ident("len_of") {
+            Self::LenOf(attr.parse_args()?)
+        } else if path.is_ident("size_of_rest") {
+            let from = match &attr.meta {
+                syn::Meta::Path(_) => None,
+                _ => Some(attr.parse_args::<SizeOfRestArgs>()?.from),
+            };
+            Self::SizeOfRest { from }
+        } else if path.is_ident("constant") {
+            let syn::Meta::NameValue(nv) = &attr.meta else {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "Expecting e.g.: #[constant = 0x42]",
+                ));
+            };
+            Self::Constant(nv.value.clone())
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(ret))
+    }
+
+    /// Remove the computed field attribute (if any) from the field
+    pub fn take(field: &mut syn::Field) -> Option<Self> {
+        let mut ret = None;
+        field.attrs.retain(|attr| {
+            let Some(parsed) = FieldAttr::parse(attr).unwrap() else {
+                return true;
+            };
+            if ret.is_some() {
+                panic!(
+                    "Field `{}` has multiple computed field attributes",
+                    field.ident.as_ref().unwrap()
+                );
+            }
+            ret = Some(parsed);
+            false
+        });
+        ret
+    }
+}
+
+/// Generate bincode Encode, Decode and BorrowDecode for a packet with
+/// computed fields
+pub fn gen_bincode_impls(
+    packet_ident: &syn::Ident,
+    fields: &[syn::Field],
+    attrs: &[Option<FieldAttr>],
+) -> TokenStream {
+    let idents: Vec<&syn::Ident> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+    let field_idx = |ident: &syn::Ident| {
+        idents
+            .iter()
+            .position(|i| *i == ident)
+            .unwrap_or_else(|| panic!("No such field: `{ident}`"))
+    };
+    let field_name = |ident: &syn::Ident| {
+        let name = format!("{packet_ident}.{ident}");
+        quote! { #name }
+    };
+
+    let mut encode_stmts = Vec::new();
+    let mut checks = Vec::new();
+    // vector field -> the field holding its length
+    let mut len_fields: Vec<Option<&syn::Ident>> = vec![None; fields.len()];
+
+    for (idx, (field, attr)) in fields.iter().zip(attrs).enumerate() {
+        let ident = idents[idx];
+        let ty = &field.ty;
+        let name = field_name(ident);
+        match attr {
+            None => {
+                encode_stmts.push(quote! {
+                    ::bincode::Encode::encode(&self.#ident, encoder)?;
+                });
+            }
+            Some(FieldAttr::LenOf(target)) => {
+                let target_idx = field_idx(target);
+                if target_idx <= idx {
+                    panic!("#[len_of({target})] must precede the `{target}` field");
+                }
+                len_fields[target_idx] = Some(ident);
+                encode_stmts.push(quote! {
+                    let #ident: #ty = crate::computed_len(#name, self.#target.len())?;
+                    ::bincode::Encode::encode(&#ident, encoder)?;
+                });
+            }
+            Some(FieldAttr::SizeOfRest { from }) => {
+                let from_idx = from.as_ref().map(field_idx).unwrap_or(idx + 1);
+                let rest = &idents[from_idx..];
+                encode_stmts.push(quote! {
+                    let #ident: #ty =
+                        crate::computed_len(#name, 0 #(+ crate::encoded_len(&self.#rest))*)?;
+                    ::bincode::Encode::encode(&#ident, encoder)?;
+                });
+                checks.push(quote! {
+                    crate::check_len(#name, #ident, 0 #(+ crate::encoded_len(&#rest))*)?;
+                });
+            }
+            Some(FieldAttr::Constant(value)) => {
+                encode_stmts.push(quote! {
+                    let #ident: #ty = #value;
+                    ::bincode::Encode::encode(&#ident, encoder)?;
+                });
+                checks.push(quote! {
+                    crate::check_constant(#name, &#ident, &{ let val: #ty = #value; val })?;
+                });
+            }
+        }
+    }
+
+    let decode_stmts = |borrow: bool| {
+        fields
+            .iter()
+            .zip(&len_fields)
+            .map(move |(field, len_field)| {
+                let ident = field.ident.as_ref().unwrap();
+                let ty = &field.ty;
+                match (len_field, borrow) {
+                    (Some(len), false) => quote! {
+                        let #ident = <#ty>::decode_n(decoder, #len as usize)?;
+                    },
+                    (Some(len), true) => quote! {
+                        let #ident = <#ty>::borrow_decode_n(decoder, #len as usize)?;
+                    },
+                    (None, false) => quote! {
+                        let #ident: #ty = ::bincode::Decode::decode(decoder)?;
+                    },
+                    (None, true) => quote! {
+                        let #ident: #ty = ::bincode::BorrowDecode::borrow_decode(decoder)?;
+                    },
+                }
+            })
+    };
+    let decode = decode_stmts(false);
+    let borrow_decode = decode_stmts(true);
+
+    quote! {
+        impl ::bincode::Encode for #packet_ident {
+            fn encode<E: ::bincode::enc::Encoder>(
+                &self,
+                encoder: &mut E,
+            ) -> std::result::Result<(), ::bincode::error::EncodeError> {
+                #(#encode_stmts)*
+                Ok(())
+            }
+        }
+
+        impl ::bincode::Decode for #packet_ident {
+            fn decode<D: ::bincode::de::Decoder>(
+                decoder: &mut D,
+            ) -> std::result::Result<Self, ::bincode::error::DecodeError> {
+                #(#decode)*
+                #(#checks)*
+                Ok(Self { #(#idents),* })
+            }
+        }
+
+        impl<'a> ::bincode::BorrowDecode<'a> for #packet_ident {
+            fn borrow_decode<D: ::bincode::de::BorrowDecoder<'a>>(
+                decoder: &mut D,
+            ) -> std::result::Result<Self, ::bincode::error::DecodeError> {
+                #(#borrow_decode)*
+                #(#checks)*
+                Ok(Self { #(#idents),* })
+            }
+        }
+    }
+}
+
+/// Make the arbitrary values of computed fields consistent with the rest
+/// of the packet, so it serializes and deserializes back to the same value
+pub fn gen_arbitrary_fixups(fields: &[syn::Field], attrs: &[Option<FieldAttr>]) -> TokenStream {
+    let idents: Vec<&syn::Ident> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+    let mut fixups = Vec::new();
+    // sizes depend on the other fields, so compute them last
+    let mut size_fixups = Vec::new();
+
+    for (idx, (field, attr)) in fields.iter().zip(attrs).enumerate() {
+        let ident = idents[idx];
+        let ty = &field.ty;
+        match attr {
+            None => {}
+            Some(FieldAttr::LenOf(target)) => fixups.push(quote! {
+                ret.#target.truncate(<#ty>::MAX as usize);
+                ret.#ident = ret.#target.len() as #ty;
+            }),
+            Some(FieldAttr::SizeOfRest { from }) => {
+                let from_idx = match from {
+                    Some(from) => idents.iter().position(|i| *i == from).unwrap(),
+                    None => idx + 1,
+                };
+                let rest = &idents[from_idx..];
+                size_fixups.push(quote! {
+                    ret.#ident = (0 #(+ crate::encoded_len(&ret.#rest))*)
+                        .try_into()
+                        .map_err(|_| ::arbitrary::Error::IncorrectFormat)?;
+                });
+            }
+            Some(FieldAttr::Constant(value)) => fixups.push(quote! {
+                ret.#ident = #value;
+            }),
+        }
+    }
+
+    quote! {
+        #(#fixups)*
+        #(#size_fixups)*
+    }
+}
@@ -11,6 +11,7 @@ extern crate syn;
 extern crate quote;
 
 mod enum_parse;
+mod field_attr;
 
 #[allow(clippy::from_str_radix_10)]
 fn parse_int(str: &str) -> Result<usize, std::num::ParseIntError> {
@@ -21,19 +22,52 @@ fn parse_int(str: &str) -> Result<usize, std::num::ParseIntError> {
     }
 }
 
+/// #[packet(ID, size = EXPR)]
+/// Both parts are optional, e.g. #[packet(size = 4)]
+/// Fields can be marked as computed, see [`field_attr`]
+#[derive(Default)]
+struct PacketAttribute {
+    id: Option<usize>,
+    /// Expected length of the serialized default packet
+    size: Option<syn::Expr>,
+}
+
+impl syn::parse::Parse for PacketAttribute {
+    fn parse(input: syn::parse::ParseStream) -> syn::parse::Result<Self> {
+        let mut ret = PacketAttribute::default();
+        if input.peek(syn::LitInt) {
+            let lit = input.parse::<syn::LitInt>()?;
+            let id = parse_int(&lit.to_string()).map_err(|_| {
+                syn::parse::Error::new_spanned(
+                    &lit,
+                    "Malformed ID attribute. Expecting e.g.: #[packet(0x42)]",
+                )
+            })?;
+            ret.id = Some(id);
+            if input.is_empty() {
+                return Ok(ret);
+            }
+            input.parse::<syn::Token![,]>()?;
+        }
+
+        if !input.is_empty() {
+            let name = input.parse::<syn::Ident>()?;
+            if name != "size" {
+                return Err(syn::parse::Error::new_spanned(name, "Unknown attribute"));
+            }
+            input.parse::<syn::Token![=]>()?;
+            ret.size = Some(input.parse()?);
+        }
+        Ok(ret)
+    }
+}
+
 #[proc_macro_attribute]
 pub fn packet(
     attr: proc_macro::TokenStream,
     input: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
-    let id = if !attr.to_string().is_empty() {
-        Some(
-            parse_int(attr.to_string().as_str())
-                .expect("Malformed ID attribute. Expecting e.g.: #[packet(0x42)]"),
-        )
-    } else {
-        None
-    };
+    let PacketAttribute { id, size } = syn::parse_macro_input!(attr as PacketAttribute);
 
     let ast = syn::parse_macro_input!(input as syn::DeriveInput);
     let packet_vis = ast.vis;
@@ -47,30 +81,146 @@ pub fn packet(
         _ => panic!("#[packet] expects a struct"),
     };
 
-    // Set visibility to each field
+    // Strip the computed field attributes, they're handled below
+    let field_attrs: Vec<_> = fields.iter_mut().map(field_attr::FieldAttr::take).collect();
+    let has_computed_fields = field_attrs.iter().any(Option::is_some);
+
+    // Set visibility to each field, and serialize plain byte arrays as hex
     for f in fields.iter_mut() {
         f.vis = packet_vis.clone();
+        if is_byte_array(&f.ty) {
+            f.attrs
+                .push(syn::parse_quote!(#[serde(with = "crate::serde_impls::hex_array")]));
+        }
     }
 
+    let bincode_derives = if has_computed_fields {
+        quote! {}
+    } else {
+        quote! { ::bincode::Encode, ::bincode::Decode, }
+    };
+
     // Re-create the original struct
     let mut ret_stream = quote! {
         #(#packet_attrs)*
-        #[derive(std::fmt::Debug, PartialEq, Clone, Default, ::bincode::Encode, ::bincode::Decode)]
+        #[derive(std::fmt::Debug, PartialEq, Clone, Default, #bincode_derives ::serde::Serialize, ::serde::Deserialize)]
         #packet_vis struct #packet_ident #impl_generics #where_clause {
             #(#fields),*
         }
     };
 
+    if has_computed_fields {
+        if !ast.generics.params.is_empty() {
+            panic!("Computed fields are not supported in generic packets");
+        }
+        ret_stream.extend(field_attr::gen_bincode_impls(
+            &packet_ident,
+            &fields,
+            &field_attrs,
+        ));
+    }
+
+    let field_idents = fields.iter().map(|f| f.ident.as_ref().unwrap());
+    ret_stream.extend(quote! {
+        impl #impl_generics crate::PacketLayout for #packet_ident #type_generics #where_clause {
+            fn layout(&self) -> Vec<crate::FieldLayout> {
+                let mut layout = Vec::new();
+                #(crate::FieldLayout::push(&mut layout, stringify!(#field_idents), &self.#field_idents);)*
+                layout
+            }
+        }
+    });
+
+    // Generic packets are only wrappers around other packets
+    if ast.generics.params.is_empty() {
+        let field_tys = fields.iter().map(|f| &f.ty);
+        ret_stream.extend(quote! {
+            impl crate::FixedSize for #packet_ident {
+                const FIXED_SIZE: Option<usize> = {
+                    let size = Some(0);
+                    #(
+                        let size = match (size, <#field_tys as crate::FixedSize>::FIXED_SIZE) {
+                            (Some(size), Some(field_size)) => Some(size + field_size),
+                            _ => None,
+                        };
+                    )*
+                    size
+                };
+            }
+        });
+
+        let field_idents = fields.iter().map(|f| f.ident.as_ref().unwrap());
+        let fixups = field_attr::gen_arbitrary_fixups(&fields, &field_attrs);
+        ret_stream.extend(quote! {
+            #[cfg(any(test, feature = "arbitrary"))]
+            impl<'a> ::arbitrary::Arbitrary<'a> for #packet_ident {
+                fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<Self> {
+                    #[allow(unused_mut)]
+                    let mut ret = Self {
+                        #(#field_idents: ::arbitrary::Arbitrary::arbitrary(u)?,)*
+                    };
+                    #fixups
+                    Ok(ret)
+                }
+            }
+        });
+    }
+
+    if let Some(size) = &size {
+        if !ast.generics.params.is_empty() {
+            panic!("size = ... is not supported for generic packets");
+        }
+        let test_mod = format_ident!("{}_test_def_packet_size", packet_ident);
+        ret_stream.extend(quote! {
+            #[cfg(test)]
+            #[allow(non_snake_case)]
+            mod #test_mod {
+                use super::*;
+                // sizes are often given as `TOTAL - Header::SIZE`
+                #[allow(unused_imports)]
+                use crate::Header;
+
+                #[test]
+                fn test() {
+                    let p = #packet_ident::default();
+                    let mut buf = Vec::new();
+                    let len = ::bincode::encode_into_std_write(&p, &mut buf, ::bincode::config::legacy()).unwrap();
+                    assert_eq!(len, #size);
+
+                    let layout = crate::PacketLayout::layout(&p);
+                    let layout_len = layout.last().map(|f| f.range().end).unwrap_or(0);
+                    assert_eq!(layout_len, #size);
+                }
+            }
+        });
+    }
+
     if let Some(id) = id {
         let Ok(id) = u16::try_from(id) else {
             panic!("Packet ID greater than u16::MAX");
         };
 
+        // `size` is the length of the default packet, which is only the
+        // length of every packet if none of the fields can grow
+        let size_const = size.map(|size| {
+            quote! {
+                const SIZE: Option<usize> = match <Self as crate::FixedSize>::FIXED_SIZE {
+                    Some(_) => Some({
+                        #[allow(unused_imports)]
+                        use crate::Header;
+                        #size
+                    }),
+                    None => None,
+                };
+            }
+        });
         ret_stream.extend(quote! {
             impl #packet_ident #type_generics #where_clause {
                 pub const ID: u16 = #id;
             }
             impl #impl_generics crate::Payload for #packet_ident #type_generics #where_clause {
+                #size_const
+
                 fn id(&self) -> u16 {
                     Self::ID
                 }
@@ -81,9 +231,21 @@ pub fn packet(
     ret_stream.into()
 }
 
+/// Check if the type is `[u8; N]`
+fn is_byte_array(ty: &syn::Type) -> bool {
+    let syn::Type::Array(arr) = ty else {
+        return false;
+    };
+    matches!(&*arr.elem, syn::Type::Path(p) if p.path.is_ident("u8"))
+}
+
 #[proc_macro_derive(PacketEnum)]
 pub fn derive_packet_enum(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let enum_parse::EnumInfo { name, repr } = syn::parse_macro_input!(input);
+    let enum_parse::EnumInfo {
+        name,
+        repr,
+        variants,
+    } = syn::parse_macro_input!(input);
 
     quote! {
         use ::bincode::enc::write::Writer;
@@ -113,33 +275,76 @@ pub fn derive_packet_enum(input: proc_macro::TokenStream) -> proc_macro::TokenSt
 
         impl<'a> ::bincode::BorrowDecode<'a> for #name
         {
-            fn borrow_decode<D: ::bincode::de::BorrowDecoder<'a>>(_decoder: &mut D) -> Result<Self, ::bincode::error::DecodeError> {
-                unimplemented!();
+            fn borrow_decode<D: ::bincode::de::BorrowDecoder<'a>>(decoder: &mut D) -> Result<Self, ::bincode::error::DecodeError> {
+                <Self as ::bincode::Decode>::decode(decoder)
+            }
+        }
+
+        impl crate::FixedSize for #name {
+            const FIXED_SIZE: Option<usize> = Some(std::mem::size_of::<#repr>());
+        }
+
+        #[cfg(any(test, feature = "arbitrary"))]
+        impl<'a> ::arbitrary::Arbitrary<'a> for #name {
+            fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<Self> {
+                Ok(u.choose(&[#(Self::#variants),*])?.clone())
             }
         }
     }.into()
 }
 
-/// #[path = path::to::PacketType]
-struct PathAttribute {
-    path: syn::Path,
+/// #[attr(path = path::to::PacketType, from = ServiceID, to = ServiceID)]
+/// All keys are optional.
+#[derive(Default)]
+struct VariantAttribute {
+    path: Option<syn::Path>,
+    from: Option<syn::Ident>,
+    to: Option<syn::Ident>,
 }
 
 struct Packet {
     name: syn::Ident,
     path: proc_macro2::TokenStream,
+    /// Sender service of the packet, if the ID alone is ambiguous
+    from: Option<syn::Ident>,
+    /// Receiver service of the packet, if the ID alone is ambiguous
+    to: Option<syn::Ident>,
     span: proc_macro2::Span,
 }
 
-impl syn::parse::Parse for PathAttribute {
+impl Packet {
+    /// Packets with no direction are the fallback for IDs
+    /// that don't match any packets with direction
+    fn has_direction(&self) -> bool {
+        self.from.is_some() || self.to.is_some()
+    }
+
+    fn same_direction(&self, other: &Packet) -> bool {
+        self.from == other.from && self.to == other.to
+    }
+}
+
+impl syn::parse::Parse for VariantAttribute {
     fn parse(input: syn::parse::ParseStream) -> syn::parse::Result<Self> {
-        let name = input.parse::<syn::Ident>()?;
-        if name != "path" {
-            return Err(syn::parse::Error::new_spanned(name, "Unknown attribute"));
+        let mut ret = VariantAttribute::default();
+        loop {
+            let name = input.parse::<syn::Ident>()?;
+            input.parse::<syn::Token![=]>()?;
+            if name == "path" {
+                ret.path = Some(input.parse()?);
+            } else if name == "from" {
+                ret.from = Some(input.parse()?);
+            } else if name == "to" {
+                ret.to = Some(input.parse()?);
+            } else {
+                return Err(syn::parse::Error::new_spanned(name, "Unknown attribute"));
+            }
+
+            if input.is_empty() {
+                return Ok(ret);
+            }
+            input.parse::<syn::Token![,]>()?;
         }
-        input.parse::<syn::Token![=]>()?;
-        let path: syn::Path = input.parse()?;
-        Ok(PathAttribute { path })
     }
 }
 
@@ -163,13 +368,21 @@ pub fn packet_list(
         .map(|variant| {
             let span = variant.span();
             let name = variant.ident;
-            let mut path = None;
+            let mut attrs = VariantAttribute::default();
             for attr in variant.attrs.iter() {
-                let attr_path = attr.parse_args::<PathAttribute>().unwrap();
-                path = Some(attr_path.path.into_token_stream());
+                attrs = attr.parse_args::<VariantAttribute>().unwrap();
+            }
+            let path = attrs
+                .path
+                .map(|path| path.into_token_stream())
+                .unwrap_or(name.to_token_stream());
+            Packet {
+                name,
+                path,
+                from: attrs.from,
+                to: attrs.to,
+                span,
             }
-            let path = path.unwrap_or(name.to_token_stream());
-            Packet { name, path, span }
         })
         .collect();
 
@@ -182,7 +395,7 @@ pub fn packet_list(
     });
     let mut ret_stream = quote! {
         #(#enum_attrs)*
-        #[derive(PartialEq, Clone)]
+        #[derive(PartialEq, Clone, ::serde::Serialize, ::serde::Deserialize)]
         #enum_vis enum #enum_name {
             Unknown(Unknown),
             #(#enum_variants)*
@@ -195,31 +408,120 @@ pub fn packet_list(
             Self :: #name ( inner ) => inner.id(),
         }
     });
+    let layout_match_arms = packets.iter().map(|packet| {
+        let name = &packet.name;
+        quote_spanned! { packet.span =>
+            Self :: #name ( inner ) => crate::PacketLayout::layout(inner),
+        }
+    });
+    let service_id = |service: &Option<syn::Ident>| match service {
+        Some(service) => quote! { Some(crate::pkt_common::ServiceID::#service) },
+        None => quote! { None },
+    };
+    let context_match_arms = packets.iter().map(|packet| {
+        let name = &packet.name;
+        let from = service_id(&packet.from);
+        let to = service_id(&packet.to);
+        quote_spanned! { packet.span =>
+            Self :: #name ( _ ) => crate::PacketContext::new(#from, #to),
+        }
+    });
+    let num_types = packets.len();
     ret_stream.extend(quote! {
 
         impl #enum_name {
+            /// Number of known packet types, i.e. all but [`Self::Unknown`]
+            pub const NUM_TYPES: usize = #num_types;
+
+            /// Sender and receiver of this packet type, if they're needed
+            /// to tell it apart from other packets with the same ID.
+            pub fn context(&self) -> crate::PacketContext {
+                match self {
+                    Self::Unknown(_) => crate::PacketContext::default(),
+                    #(#context_match_arms)*
+                }
+            }
+
             pub fn id(&self) -> u16 {
                 match self {
                     Self::Unknown(inner) => inner.id(),
                     #(#id_match_arms)*
                 }
             }
+
+            /// Layout of the serialized payload. Unknown packets are
+            /// described as a single `data` field.
+            pub fn layout(&self) -> Vec<crate::FieldLayout> {
+                match self {
+                    Self::Unknown(inner) => vec![crate::FieldLayout {
+                        name: "data",
+                        offset: 0,
+                        len: inner.data.len(),
+                    }],
+                    #(#layout_match_arms)*
+                }
+            }
         }
     });
 
-    let deser_match_arms = packets.iter().map(|packet| {
+    let deser_match_arms = packets
+        .iter()
+        .filter(|packet| !packet.has_direction())
+        .map(|packet| {
+            let name = &packet.name;
+            let path = &packet.path;
+            quote_spanned! { packet.span =>
+                #path :: ID => Self :: #name ( _deserialize::<#path>(data)? ),
+            }
+        });
+
+    // Try the packets constrained by both sender and receiver first
+    let mut directed_packets: Vec<&Packet> = packets
+        .iter()
+        .filter(|packet| packet.has_direction())
+        .collect();
+    directed_packets.sort_by_key(|packet| !(packet.from.is_some() && packet.to.is_some()));
+    let deser_ctx_arms = directed_packets.iter().map(|packet| {
         let name = &packet.name;
         let path = &packet.path;
+        let from = service_id(&packet.from);
+        let to = service_id(&packet.to);
         quote_spanned! { packet.span =>
-            #path :: ID => Self :: #name ( _deserialize::<#path>(data)? ),
+            if id == #path :: ID && ctx.matches(#from, #to) {
+                return Ok(Self :: #name ( _deserialize::<#path>(data)? ));
+            }
         }
     });
+
+    let size_match_arms = packets
+        .iter()
+        .filter(|packet| !packet.has_direction())
+        .map(|packet| {
+            let path = &packet.path;
+            quote_spanned! { packet.span =>
+                #path :: ID => <#path as crate::Payload>::SIZE,
+            }
+        });
+    let size_ctx_arms = directed_packets.iter().map(|packet| {
+        let path = &packet.path;
+        let from = service_id(&packet.from);
+        let to = service_id(&packet.to);
+        quote_spanned! { packet.span =>
+            if id == #path :: ID && ctx.matches(#from, #to) {
+                return <#path as crate::Payload>::SIZE;
+            }
+        }
+    });
+
+    let deserialize_fn = quote! {
+        fn _deserialize<P: crate::Payload + for<'a> ::bincode::BorrowDecode<'a>>(data: &[u8]) -> Result<P, crate::PayloadDeserializeError> {
+            P::deserialize_no_hdr(data)
+        }
+    };
     ret_stream.extend(quote! {
         impl #enum_name {
             pub fn deserialize_no_hdr(id: u16, data: &[u8]) -> Result<Self, crate::PayloadDeserializeError> {
-                fn _deserialize<P: crate::Payload>(data: &[u8]) -> Result<P, crate::PayloadDeserializeError> {
-                    P::deserialize_no_hdr(data)
-                }
+                #deserialize_fn
 
                 Ok(match id {
                     #(#deser_match_arms)*
@@ -228,6 +530,92 @@ pub fn packet_list(
                     }
                 })
             }
+
+            /// Deserialize a packet whose ID might be shared by multiple
+            /// packet types. The one matching the `ctx` is picked, or the
+            /// one without any sender/receiver if nothing matches.
+            pub fn deserialize_no_hdr_ctx(ctx: &crate::PacketContext, id: u16, data: &[u8]) -> Result<Self, crate::PayloadDeserializeError> {
+                #deserialize_fn
+
+                #(#deser_ctx_arms)*
+                Self::deserialize_no_hdr(id, data)
+            }
+
+            /// Payload size of the packet type that would be deserialized
+            /// with [`Self::deserialize_no_hdr_ctx`], if the size is fixed.
+            pub fn payload_size(ctx: &crate::PacketContext, id: u16) -> Option<usize> {
+                #(#size_ctx_arms)*
+                match id {
+                    #(#size_match_arms)*
+                    _ => None,
+                }
+            }
+        }
+    });
+
+    // Each ID can be used only once per sender/receiver pair.
+    // The IDs are only known after macro expansion, so check them
+    // at compile time.
+    let mut groups: Vec<Vec<&Packet>> = Vec::new();
+    for packet in &packets {
+        match groups.iter_mut().find(|g| g[0].same_direction(packet)) {
+            Some(group) => group.push(packet),
+            None => groups.push(vec![packet]),
+        }
+    }
+    for group in groups {
+        let paths = group.iter().map(|packet| &packet.path);
+        let num_packets = group.len();
+        let asserts = group.iter().enumerate().map(|(idx, packet)| {
+            let name = &packet.name;
+            quote_spanned! { packet.span =>
+                assert!(is_unique(#idx), concat!("Packet ID of ", stringify!(#name), " is already used by another packet with the same sender/receiver"));
+            }
+        });
+        ret_stream.extend(quote! {
+            const _: () = {
+                const IDS: [u16; #num_packets] = [#(#paths::ID),*];
+                const fn is_unique(idx: usize) -> bool {
+                    let mut i = 0;
+                    while i < idx {
+                        if IDS[i] == IDS[idx] {
+                            return false;
+                        }
+                        i += 1;
+                    }
+                    true
+                }
+                #(#asserts)*
+            };
+        });
+    }
+
+    let arbitrary_match_arms = packets.iter().enumerate().map(|(idx, packet)| {
+        let name = &packet.name;
+        quote_spanned! { packet.span =>
+            #idx => Self :: #name ( ::arbitrary::Arbitrary::arbitrary(u)? ),
+        }
+    });
+    ret_stream.extend(quote! {
+        #[cfg(any(test, feature = "arbitrary"))]
+        impl #enum_name {
+            /// Generate an arbitrary packet of the `idx`-th known type,
+            /// `idx` < [`Self::NUM_TYPES`]
+            pub fn arbitrary_of_type(idx: usize, u: &mut ::arbitrary::Unstructured) -> ::arbitrary::Result<Self> {
+                Ok(match idx {
+                    #(#arbitrary_match_arms)*
+                    _ => panic!("Packet type index out of range: {idx}"),
+                })
+            }
+        }
+
+        /// Never generates [`Self::Unknown`] packets
+        #[cfg(any(test, feature = "arbitrary"))]
+        impl<'a> ::arbitrary::Arbitrary<'a> for #enum_name {
+            fn arbitrary(u: &mut ::arbitrary::Unstructured<'a>) -> ::arbitrary::Result<Self> {
+                let idx = u.choose_index(Self::NUM_TYPES)?;
+                Self::arbitrary_of_type(idx, u)
+            }
         }
     });
 
@@ -248,10 +636,10 @@ pub fn packet_list(
                 }
 
                 let ctx = dst;
-                Ok(match self {
+                match self {
                     Self::Unknown(inner) => _process(inner, ctx),
                     #(#ser_match_arms)*
-                }?)
+                }
             }
 
             fn id(&self) -> u16 {
@@ -290,26 +678,37 @@ pub fn packet_list(
             }
         }
 
+        /// Encodes just the payload, same as [`crate::Payload::serialize_no_hdr`]
         impl ::bincode::Encode for #enum_name
         {
             fn encode<E: ::bincode::enc::Encoder>(
                 &self,
                 encoder: &mut E,
             ) -> std::result::Result<(), ::bincode::error::EncodeError> {
-                unimplemented!("encode");
+                fn _process<T: ::bincode::Encode, E: ::bincode::enc::Encoder>(inner: &T, encoder: &mut E) -> std::result::Result<(), ::bincode::error::EncodeError> {
+                    inner.encode(encoder)
+                }
+
+                let ctx = encoder;
+                match self {
+                    Self::Unknown(inner) => _process(&inner.data, ctx),
+                    #(#ser_match_arms)*
+                }
             }
         }
 
+        /// The payload alone doesn't say which packet it is. Use
+        /// [`Self::deserialize`] or [`Self::deserialize_no_hdr`] instead
         impl ::bincode::Decode for #enum_name {
-            fn decode<D: ::bincode::de::Decoder>(decoder: &mut D) -> std::result::Result<Self, ::bincode::error::DecodeError> {
-                unimplemented!("decode");
+            fn decode<D: ::bincode::de::Decoder>(_decoder: &mut D) -> std::result::Result<Self, ::bincode::error::DecodeError> {
+                Err(::bincode::error::DecodeError::Other("Packet can't be decoded without its ID"))
             }
         }
 
         impl<'a> ::bincode::BorrowDecode<'a> for #enum_name
         {
             fn borrow_decode<D: ::bincode::de::BorrowDecoder<'a>>(_decoder: &mut D) -> Result<Self, ::bincode::error::DecodeError> {
-                unimplemented!();
+                Err(::bincode::error::DecodeError::Other("Packet can't be decoded without its ID"))
             }
         }
     });
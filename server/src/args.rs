@@ -1,15 +1,20 @@
 // SPDX-License-Identifier: MIT
 // Copyright(c) 2023 Darek Stojaczyk
 
-use std::{ffi::OsString, path::PathBuf, sync::OnceLock};
+use std::{ffi::OsString, path::PathBuf, sync::OnceLock, time::Duration};
 
 use clap::{Parser, Subcommand};
 
+use crate::packet_stream::KeepaliveConfig;
+
 /// Cabal Online Replacement Services
 ///
 /// You can run this as a single specific --service, or any combination of
 /// them with multiple --service arguments. If a service expects additional
 /// options, you can specify them immediately after --service.
+///
+/// There are also offline tools which don't start any service, e.g.
+/// `decode`. Run them as the very first argument instead of --service.
 #[derive(Parser, Debug)]
 #[clap(bin_name = format!("{} --service", bin_name()))]
 #[clap(version, about, long_about, verbatim_doc_comment)]
@@ -26,6 +31,8 @@ struct Args {
 #[derive(Subcommand, Debug)]
 #[clap(rename_all = "kebab_case")]
 pub enum Service {
+    #[cfg(feature = "chat")]
+    Chat(crate::chat::ChatArgs),
     #[cfg(feature = "crypto")]
     Crypto(crate::crypto::CryptoArgs),
     #[cfg(feature = "event")]
@@ -46,6 +53,30 @@ pub struct CommonConfig {
     #[arg(default_value = ".")]
     #[arg(short = 'r', long)]
     pub resources_dir: PathBuf,
+    /// Send a keepalive on every IPC connection that was idle for this many
+    /// seconds. 0 disables it.
+    /// The keepalive is EventMgr's packet (0x2b3), which the original
+    /// services don't expect on other connections. Only enable it if both
+    /// sides of every IPC link are services from this crate
+    #[arg(long, default_value_t = 0, value_name = "SECS")]
+    pub ipc_keepalive_interval: u64,
+    /// Close IPC connections that didn't receive anything for this many
+    /// seconds. 0 disables it.
+    /// The original services don't send keepalives, so idle connections to
+    /// them would be closed. Only enable it if both sides of every IPC link
+    /// are services from this crate, with --ipc-keepalive-interval set
+    #[arg(long, default_value_t = 0, value_name = "SECS")]
+    pub ipc_timeout: u64,
+}
+
+impl CommonConfig {
+    pub fn ipc_keepalive(&self) -> KeepaliveConfig {
+        let secs = |secs| (secs != 0).then(|| Duration::from_secs(secs));
+        KeepaliveConfig {
+            interval: secs(self.ipc_keepalive_interval),
+            timeout: secs(self.ipc_timeout),
+        }
+    }
 }
 
 /// The final config structure used at runtime
@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+use std::{net::TcpStream, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+use async_proc::select;
+use futures::FutureExt;
+use log::warn;
+use packet::pkt_common::ServiceID;
+use packet::*;
+use pkt_global::RegisterChatSvr;
+use smol::Async;
+
+use super::Listener;
+use crate::{
+    packet_stream::IPCPacketStream,
+    registry::{BorrowRef, Borrowable},
+};
+
+pub struct GmsHandler {
+    pub _listener: Arc<Listener>,
+    pub stream: IPCPacketStream<Async<TcpStream>>,
+    pub conn_ref: Arc<BorrowRef<Self, ()>>,
+    register: RegisterChatSvr,
+}
+crate::impl_borrowable!(
+    GmsHandler,
+    RefData = (),
+    borrow_ref = .conn_ref
+);
+
+impl GmsHandler {
+    pub fn new(
+        listener: Arc<Listener>,
+        stream: IPCPacketStream<Async<TcpStream>>,
+        conn_ref: Arc<BorrowRef<Self, ()>>,
+        register: &RegisterChatSvr,
+    ) -> Self {
+        Self {
+            _listener: listener,
+            stream,
+            conn_ref,
+            register: register.clone(),
+        }
+    }
+
+    pub async fn handle(&mut self) -> Result<()> {
+        let p = self
+            .stream
+            .recv()
+            .await
+            .map_err(|e| anyhow!("{self}: Failed to receive the first packet: {e:?}"))?;
+        let Packet::ConnectAck(ack) = p else {
+            bail!("{self}: Expected ConnectAck packet, got {p:?}");
+        };
+        if ack.bytes.get(8) != Some(&(ServiceID::GlobalMgrSvr as u8)) {
+            bail!("{self}: Unexpected ConnectAck: {ack:?}");
+        }
+
+        self.stream.send(&self.register).await?;
+
+        loop {
+            select! {
+                p = self.stream.recv().fuse() => {
+                    let p = p.map_err(|e| {
+                        anyhow!("{self}: Failed to recv a packet: {e}")
+                    })?;
+                    warn!("{self}: Got unexpected packet: {p:?}");
+                }
+                _ = self.conn_ref.borrower.wait_to_lend().fuse() => {
+                    self.lend_self().await;
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for GmsHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Gms")
+    }
+}
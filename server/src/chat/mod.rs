@@ -0,0 +1,193 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+use crate::executor;
+use crate::locked_vec::LockedVec;
+use crate::packet_stream::{IPCPacketStream, Service};
+use crate::registry::BorrowRef;
+use clap::Args;
+use log::{error, info};
+use packet::pkt_common::ServiceID;
+use packet::*;
+use pkt_common::Connect;
+use pkt_global::RegisterChatSvr;
+
+use std::net::TcpStream;
+use std::sync::Weak;
+use std::time::Duration;
+use std::{net::TcpListener, sync::Arc};
+
+use anyhow::Result;
+use smol::{Async, Task, Timer};
+
+mod gms;
+use gms::GmsHandler;
+mod world;
+use world::WorldHandler;
+
+/// ChatNode replacement. Experimental: it only registers at the GMS and
+/// accepts WorldSvr connections, but no chat is relayed yet. The chat
+/// packets weren't observed in any capture of the original services
+#[derive(Args, Debug)]
+#[command(about, long_about, verbatim_doc_comment, disable_help_flag = true)]
+pub struct ChatArgs {
+    /// Port of the GMS to register at
+    #[arg(long, default_value_t = 38170)]
+    pub gms_port: u16,
+    /// server_id sent to the GMS in RegisterChatSvr. Not verified against
+    /// the original ChatNode
+    #[arg(long, default_value_t = 1)]
+    pub server_id: u8,
+    /// channel_id sent to the GMS in RegisterChatSvr. Not verified against
+    /// the original ChatNode
+    #[arg(long, default_value_t = 1)]
+    pub channel_id: u8,
+}
+
+pub struct Listener {
+    me: Weak<Listener>,
+    args: Arc<crate::args::Config>,
+    tcp_listener: Async<TcpListener>,
+    worlds: LockedVec<Arc<BorrowRef<WorldHandler, Connect>>>,
+    /// Empty while not connected
+    gms: LockedVec<Arc<BorrowRef<GmsHandler, ()>>>,
+}
+
+impl std::fmt::Display for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "Chat:{}",
+            self.tcp_listener.get_ref().local_addr().unwrap().port()
+        ))
+    }
+}
+
+impl Listener {
+    pub fn new(tcp_listener: Async<TcpListener>, args: &Arc<crate::args::Config>) -> Arc<Self> {
+        Arc::new_cyclic(|me| Self {
+            me: me.clone(),
+            args: args.clone(),
+            tcp_listener,
+            worlds: LockedVec::new(),
+            gms: LockedVec::new(),
+        })
+    }
+
+    pub async fn listen(&self) -> Result<()> {
+        let port = self.tcp_listener.get_ref().local_addr()?.port();
+        info!("Listener: started on {port}");
+        let chat_args = self
+            .args
+            .services
+            .iter()
+            .find_map(|s| {
+                if let crate::args::Service::Chat(args) = s {
+                    Some(args)
+                } else {
+                    None
+                }
+            })
+            .unwrap();
+
+        // stop reconnecting together with the listener
+        let _gms_task = self.connect_to_gms(chat_args, port);
+        let keepalive = self.args.common.ipc_keepalive();
+
+        loop {
+            let (stream, _) = self.tcp_listener.accept().await?;
+            let listener = self.me.upgrade().unwrap();
+            // Give the connection handler its own background task
+            executor::spawn_local(async move {
+                info!("Listener: new connection ...");
+
+                let stream =
+                    match IPCPacketStream::from_host(Service::ChatNode, stream, keepalive).await {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            error!("Listener: {e}. Closing");
+                            return;
+                        }
+                    };
+
+                let id = stream.other_id;
+                let Service::WorldSvr { .. } = id else {
+                    error!("Listener: expected WorldSvr connection, got {id}. Closing");
+                    return;
+                };
+
+                info!("Listener: {id} connected");
+                let conn_ref = BorrowRef::new(Connect::from(id));
+                listener.worlds.push(conn_ref.clone());
+                let ret = WorldHandler::new(listener.clone(), stream, conn_ref.clone())
+                    .handle()
+                    .await;
+                if let Err(err) = ret {
+                    error!("Listener: {id} error: {err}");
+                } else {
+                    info!("Listener: closing {id}");
+                }
+                listener
+                    .worlds
+                    .lock_write()
+                    .retain(|c| !Arc::ptr_eq(c, &conn_ref));
+            })
+            .detach();
+        }
+    }
+
+    /// Whether the GMS connection is up
+    pub fn gms_connected(&self) -> bool {
+        !self.gms.is_empty()
+    }
+
+    fn connect_to_gms(&self, chat_args: &ChatArgs, port: u16) -> Task<()> {
+        let listener = self.me.upgrade().unwrap();
+        let keepalive = self.args.common.ipc_keepalive();
+        let gms_port = chat_args.gms_port;
+        let register = RegisterChatSvr {
+            server_id: chat_args.server_id,
+            channel_id: chat_args.channel_id,
+            chattype: ServiceID::ChatNode as u8,
+            unk1: 0,
+            port,
+        };
+
+        executor::spawn_local(async move {
+            loop {
+                let Ok(stream) = Async::<TcpStream>::connect(([127, 0, 0, 1], gms_port)).await
+                else {
+                    Timer::after(Duration::from_secs(2)).await;
+                    continue;
+                };
+
+                info!("Listener: GMS connection established");
+                let stream = match IPCPacketStream::from_conn(
+                    Service::ChatNode,
+                    Service::GlobalMgrSvr { id: 0x80 },
+                    stream,
+                    keepalive,
+                )
+                .await
+                {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        error!("Listener: {err}");
+                        Timer::after(Duration::from_secs(2)).await;
+                        continue;
+                    }
+                };
+
+                let conn_ref = BorrowRef::new(());
+                listener.gms.push(conn_ref.clone());
+                let ret = GmsHandler::new(listener.clone(), stream, conn_ref.clone(), &register)
+                    .handle()
+                    .await;
+                listener
+                    .gms
+                    .lock_write()
+                    .retain(|c| !Arc::ptr_eq(c, &conn_ref));
+                info!("Listener: GMS connection closed => {ret:?}");
+            }
+        })
+    }
+}
@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+//! ESYM files served to WorldSvr-s, picked by the nation and srchash the
+//! WorldSvr asks for. The mapping is kept in `resources/esym/index.json`:
+//! ```json
+//! [
+//!   { "nation": "BRA", "srchash": "f2b76e1ee8a92a8ce99a41c07926d3f3",
+//!     "file": "f2b76e1ee8a92a8ce99a41c07926d3f3.esym" }
+//! ]
+//! ```
+//! Without the index, `resources/esym/<srchash>.esym` files are served
+//! instead, regardless of the nation.
+//!
+//! Either way, all files are read and validated once, then kept in memory.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use packet::pkt_crypto::ESYMResponse;
+use packet::{BoundVec, Header};
+use serde::Deserialize;
+
+/// unk1 + filesize of ESYMResponse
+const ESYM_RESPONSE_HDR_SIZE: usize = 8;
+/// The whole file has to fit in a single ESYMResponse packet
+pub const MAX_ESYM_LEN: usize = u16::MAX as usize - Header::SIZE - ESYM_RESPONSE_HDR_SIZE;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IndexEntry {
+    nation: String,
+    srchash: String,
+    /// Relative to the index file
+    file: PathBuf,
+}
+
+/// The responses are built once, so they can be sent without copying the
+/// file contents on each request
+#[derive(Debug, Default)]
+pub struct EsymIndex {
+    /// (nation, srchash) -> response
+    files: HashMap<(String, String), Arc<ESYMResponse>>,
+    /// srchash -> response, used instead if there's no index
+    fallback: Option<HashMap<String, Arc<ESYMResponse>>>,
+}
+
+impl EsymIndex {
+    pub fn path(resources_dir: &Path) -> PathBuf {
+        resources_dir.join("resources/esym/index.json")
+    }
+
+    /// Read the index and all files it refers to. Fails if any entry is
+    /// invalid.
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("cannot read {path:?}"))?;
+        let entries: Vec<IndexEntry> =
+            serde_json::from_slice(&data).with_context(|| format!("cannot parse {path:?}"))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        // the same file can be used for multiple nations, read it just once
+        let mut cache: HashMap<PathBuf, Arc<ESYMResponse>> = HashMap::new();
+        let mut files = HashMap::new();
+        for entry in entries {
+            let file_path = dir.join(&entry.file);
+            let contents = match cache.get(&file_path) {
+                Some(contents) => contents.clone(),
+                None => {
+                    let contents = esym_response(read_esym(&file_path)?);
+                    cache.insert(file_path, contents.clone());
+                    contents
+                }
+            };
+
+            let key = (entry.nation, entry.srchash.to_ascii_lowercase());
+            if files.contains_key(&key) {
+                bail!(
+                    "{path:?}: duplicate entry for nation {} and srchash {}",
+                    key.0,
+                    key.1
+                );
+            }
+            files.insert(key, contents);
+        }
+
+        Ok(Self {
+            files,
+            fallback: None,
+        })
+    }
+
+    /// Read all `<srchash>.esym` files in the given directory, since there's
+    /// no index. Fails if any of them is invalid.
+    pub fn fallback(dir: &Path) -> Result<Self> {
+        let mut files = HashMap::new();
+        for entry in std::fs::read_dir(dir).with_context(|| format!("cannot read {dir:?}"))? {
+            let path = entry
+                .with_context(|| format!("cannot read {dir:?}"))?
+                .path();
+            if path.extension() != Some(OsStr::new("esym")) {
+                continue;
+            }
+            let srchash = path.file_stem().and_then(OsStr::to_str).unwrap_or_default();
+            if srchash.is_empty() || !srchash.chars().all(|c| c.is_ascii_hexdigit()) {
+                bail!("{path:?}: the file name should be a srchash");
+            }
+            files.insert(
+                srchash.to_ascii_lowercase(),
+                esym_response(read_esym(&path)?),
+            );
+        }
+
+        Ok(Self {
+            files: HashMap::new(),
+            fallback: Some(files),
+        })
+    }
+
+    pub fn get(&self, nation: &str, srchash: &str) -> Result<Arc<ESYMResponse>> {
+        let srchash = srchash.to_ascii_lowercase();
+        if let Some(files) = &self.fallback {
+            return match files.get(&srchash) {
+                Some(resp) => Ok(resp.clone()),
+                None => bail!("no {srchash}.esym"),
+            };
+        }
+
+        match self.files.get(&(nation.to_string(), srchash.clone())) {
+            Some(resp) => Ok(resp.clone()),
+            None => bail!("no ESYM for nation {nation} and srchash {srchash} in the index"),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.fallback {
+            Some(files) => files.len(),
+            None => self.files.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn esym_response(data: Vec<u8>) -> Arc<ESYMResponse> {
+    Arc::new(ESYMResponse {
+        unk1: 0x1,
+        filesize: data.len() as u32,
+        esym: BoundVec(data),
+    })
+}
+
+fn read_esym(path: &Path) -> Result<Vec<u8>> {
+    let data = std::fs::read(path).with_context(|| format!("cannot read {path:?}"))?;
+    if data.is_empty() {
+        bail!("{path:?} is empty");
+    }
+    if data.len() > MAX_ESYM_LEN {
+        bail!(
+            "{path:?} is too big ({} bytes, max {MAX_ESYM_LEN})",
+            data.len()
+        );
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fallback() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/esym");
+        let esym = EsymIndex::fallback(&dir).unwrap();
+        assert_eq!(esym.len(), 1);
+        let srchash = "f2b76e1ee8a92a8ce99a41c07926d3f3";
+        let data = std::fs::read(dir.join(srchash).with_extension("esym")).unwrap();
+        // any nation works
+        assert_eq!(*esym.get("XYZ", srchash).unwrap().esym, data);
+        assert!(esym.get("BRA", "00000000000000000000000000000000").is_err());
+        assert!(esym.get("BRA", "../esym/index").is_err());
+    }
+}
@@ -7,13 +7,14 @@ use crate::packet_stream::{IPCPacketStream, Service};
 use crate::registry::BorrowRef;
 use aria::BlockExt;
 use clap::Args;
-use log::{debug, error, info, trace};
+use esym::EsymIndex;
+use log::{debug, error, info, trace, warn};
 use packet::*;
 
 use rand::Rng;
 use std::cell::OnceCell;
 use std::fmt::Display;
-use std::net::TcpStream;
+use std::net::{Ipv4Addr, TcpStream};
 use std::os::fd::AsRawFd;
 use std::sync::Weak;
 use std::{net::TcpListener, sync::Arc};
@@ -21,10 +22,40 @@ use std::{net::TcpListener, sync::Arc};
 use anyhow::{bail, Context, Result};
 use smol::Async;
 
+pub mod esym;
+pub mod scp;
+
 /// RockAndRoll replacement
 #[derive(Args, Debug)]
 #[command(about, long_about, verbatim_doc_comment, disable_help_flag = true)]
-pub struct CryptoArgs {}
+pub struct CryptoArgs {
+    /// DBAgent address sent to WorldSvr-s
+    #[clap(long, default_value = "127.0.0.1")]
+    pub db_ip: Ipv4Addr,
+    /// DBAgent port sent to WorldSvr-s
+    #[clap(long, default_value_t = 38180)]
+    pub db_port: u16,
+    /// Netmask each WorldSvr is expected to authenticate with. Not checked
+    /// if not specified
+    #[clap(long)]
+    pub expect_netmask: Option<String>,
+    /// Nation each WorldSvr is expected to authenticate with, e.g. BRA. Not
+    /// checked if not specified
+    #[clap(long)]
+    pub expect_nation: Option<String>,
+    /// Source hash each WorldSvr is expected to authenticate with. Not
+    /// checked if not specified
+    #[clap(long)]
+    pub expect_srchash: Option<String>,
+    /// Port each WorldSvr is expected to authenticate with. Not checked if
+    /// not specified
+    #[clap(long)]
+    pub expect_port: Option<u32>,
+    /// Close the connection if any of the above doesn't match. Otherwise
+    /// the mismatch is only logged
+    #[clap(long)]
+    pub reject_mismatch: bool,
+}
 
 pub struct Listener {
     me: Weak<Listener>,
@@ -43,11 +74,42 @@ impl Listener {
         })
     }
 
+    fn crypto_args(&self) -> &CryptoArgs {
+        self.args
+            .services
+            .iter()
+            .find_map(|s| {
+                if let crate::args::Service::Crypto(args) = s {
+                    Some(args)
+                } else {
+                    None
+                }
+            })
+            .unwrap()
+    }
+
     pub async fn listen(self: &mut Arc<Self>) -> Result<()> {
         info!(
             "Listener: started on {}",
             self.tcp_listener.get_ref().local_addr()?
         );
+        let keepalive = self.args.common.ipc_keepalive();
+
+        let esym_path = EsymIndex::path(&self.args.common.resources_dir);
+        let esym = if esym_path.exists() {
+            let esym = EsymIndex::load(&esym_path)?;
+            info!("Listener: loaded {} ESYM mapping(s)", esym.len());
+            esym
+        } else {
+            let dir = esym_path.parent().unwrap();
+            let esym = EsymIndex::fallback(dir)?;
+            info!(
+                "Listener: no {esym_path:?}, serving {} <srchash>.esym file(s) from {dir:?}",
+                esym.len()
+            );
+            esym
+        };
+        let esym = Arc::new(esym);
 
         loop {
             let (stream, _) = self.tcp_listener.accept().await?;
@@ -56,16 +118,23 @@ impl Listener {
 
             // Give the connection handler its own background task
             let listener = self.me.upgrade().unwrap();
+            let esym = esym.clone();
             executor::spawn_local(async move {
                 info!("Listener: new connection ...");
 
-                let stream = IPCPacketStream::from_host(Service::RockNRoll, stream)
-                    .await
-                    .unwrap();
+                let stream =
+                    match IPCPacketStream::from_host(Service::RockNRoll, stream, keepalive).await {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            error!("Listener: {err}. Closing");
+                            return;
+                        }
+                    };
                 let conn = Connection {
                     stream,
                     listener,
                     conn_ref,
+                    esym,
                     shortkey: OnceCell::new(),
                 };
                 let id = conn.stream.other_id;
@@ -95,6 +164,7 @@ pub struct Connection {
     pub stream: IPCPacketStream<Async<TcpStream>>,
     pub listener: Arc<Listener>,
     pub conn_ref: Arc<BorrowRef<Connection, usize>>,
+    pub esym: Arc<EsymIndex>,
     pub shortkey: OnceCell<aria::Key>,
 }
 crate::impl_borrowable!(
@@ -155,12 +225,8 @@ impl Connection {
         req.xor_port ^= 0x1f398ab3;
         debug!("{self}: auth req xor_port = {}", req.xor_port);
 
-        assert_eq!(req.unk1, 0x0);
-        assert_eq!(req.unk2, 0x0);
-
         let key = self.shortkey.get().context("shortkey not initialized")?;
-        let enckey = key.expand();
-        let deckey: aria::DecryptKey = enckey.clone().into();
+        let deckey: aria::DecryptKey = key.expand().into();
 
         xor_blocks_mut(core::slice::from_mut(&mut req.netmask));
         deckey.decrypt_mut(&mut req.netmask);
@@ -192,16 +258,50 @@ impl Connection {
             .with_context(|| format!("invalid str in binbuf: {binbuf:?}"))?;
         debug!("{self}: netmask={netmask}, nation={nation}, srchash={srchash}, binbuf={binbuf}");
 
-        let ip_local = Block::new("127.0.0.1");
-        let mut enc_item: [Block; 16] = Block::arr_from_slice("Data/Item.scp");
-        enc_item.iter_mut().for_each(|b| enckey.encrypt_mut(b));
-        xor_blocks_mut(&mut enc_item);
-        let mut enc_mobs: [Block; 16] = Block::arr_from_slice("Data/Mobs.scp");
-        enc_mobs.iter_mut().for_each(|b| enckey.encrypt_mut(b));
-        xor_blocks_mut(&mut enc_mobs);
-        let mut enc_warp: [Block; 16] = Block::arr_from_slice("Data/Warp.scp");
-        enc_warp.iter_mut().for_each(|b| enckey.encrypt_mut(b));
-        xor_blocks_mut(&mut enc_warp);
+        /// Returns true on mismatch
+        fn check<T: PartialEq + Display>(
+            conn: &Connection,
+            name: &str,
+            expected: Option<T>,
+            got: T,
+        ) -> bool {
+            match expected {
+                Some(expected) if expected != got => {
+                    warn!("{conn}: auth req {name} mismatch: expected {expected}, got {got}");
+                    true
+                }
+                _ => false,
+            }
+        }
+
+        let crypto_args = self.listener.crypto_args();
+        let mismatch = [
+            check(self, "unk1", Some(0x0), req.unk1),
+            check(self, "unk2", Some(0x0), req.unk2),
+            check(
+                self,
+                "netmask",
+                crypto_args.expect_netmask.as_deref(),
+                netmask,
+            ),
+            check(self, "nation", crypto_args.expect_nation.as_deref(), nation),
+            check(
+                self,
+                "srchash",
+                crypto_args.expect_srchash.as_deref(),
+                srchash,
+            ),
+            check(self, "port", crypto_args.expect_port, req.xor_port),
+        ]
+        .contains(&true);
+        if mismatch && crypto_args.reject_mismatch {
+            bail!("auth req mismatch, rejecting");
+        }
+
+        let ip_local = Block::new(&crypto_args.db_ip.to_string());
+        let enc_item = scp::encrypt_path(key, "Data/Item.scp")?;
+        let enc_mobs = scp::encrypt_path(key, "Data/Mobs.scp")?;
+        let enc_warp = scp::encrypt_path(key, "Data/Warp.scp")?;
 
         self.stream
             .send(&pkt_crypto::KeyAuthResponse {
@@ -214,44 +314,37 @@ impl Connection {
                 enc_mobs,
                 xor_unk5: 1 ^ 0xb3,
                 enc_warp,
-                port: 38180,
+                port: crypto_args.db_port as u32,
             })
             .await
     }
 
-    pub async fn handle_esym(&mut self, esym: pkt_crypto::ESYM) -> Result<()> {
-        let (req, len) = bincode::decode_from_slice::<pkt_crypto::ESYMRequest, _>(
-            esym.bytes.0.as_slice(),
-            bincode::config::legacy(),
-        )?;
-        if len != esym.bytes.0.len() {
-            bail!("{self}: Trailing data in ESYM packet {:#?}", esym);
-        }
-
+    pub async fn handle_esym(&mut self, req: pkt_crypto::ESYMRequest) -> Result<()> {
         debug!(
             "{self}: ESYM req nation = {}, srchash = {}",
             req.nation.0, req.srchash.0
         );
 
-        let path = self
-            .listener
-            .args
-            .common
-            .resources_dir
-            .join("resources/esym")
-            .join(req.srchash.0)
-            .with_extension("esym");
-        let data = std::fs::read(&path).with_context(|| format!("cannot read {path:?}"))?;
-
-        let r = pkt_crypto::ESYMResponse {
-            unk1: 0x1,
-            filesize: data.len() as u32,
-            esym: BoundVec(data),
+        let resp = match self.esym.get(&req.nation.0, &req.srchash.0) {
+            Ok(resp) => resp,
+            Err(err) => {
+                warn!("{self}: {err:#}");
+                // Refuse it, but keep the connection. The WorldSvr won't
+                // start without the file anyway, and will tell why in its
+                // own logs. The refusal itself is a guess though, it wasn't
+                // verified against the original RockAndRoll or WorldSvr
+                return self
+                    .stream
+                    .send(&pkt_crypto::ESYMResponse {
+                        unk1: 0x0,
+                        filesize: 0,
+                        esym: BoundVec(Vec::new()),
+                    })
+                    .await;
+            }
         };
 
-        let mut bytes = BoundVec(vec![]);
-        bincode::encode_into_std_write(r, &mut bytes.0, bincode::config::legacy())?;
-        self.stream.send(&pkt_crypto::ESYM { bytes }).await
+        self.stream.send(&*resp).await
     }
 
     pub async fn handle(mut self) -> Result<()> {
@@ -273,7 +366,7 @@ impl Connection {
             match p {
                 Packet::EncryptKey2Request(req) => self.handle_key_req(req).await?,
                 Packet::KeyAuthRequest(req) => self.handle_auth_req(req).await?,
-                Packet::ESYM(req) => self.handle_esym(req).await?,
+                Packet::ESYMRequest(req) => self.handle_esym(req).await?,
                 _ => {
                     trace!("{self}: Got packet: {p:?}");
                 }
@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+//! SCP file paths sent to WorldSvr-s in KeyAuthResponse. Each path is
+//! ARIA-encrypted with the connection's shortkey, then XOR-ed with 0xb3.
+
+use anyhow::{bail, Context, Result};
+use aria::{BlockExt, BlockSlice};
+use packet::Block;
+
+use super::xor_blocks_mut;
+
+/// Number of blocks of each encrypted path
+pub const PATH_BLOCKS: usize = 16;
+/// The path has to be null-terminated inside its blocks
+pub const MAX_PATH_LEN: usize = PATH_BLOCKS * 16 - 1;
+
+pub fn encrypt_path(key: &aria::Key, path: &str) -> Result<[Block; PATH_BLOCKS]> {
+    if path.len() > MAX_PATH_LEN {
+        bail!(
+            "path is too long ({} bytes, max {MAX_PATH_LEN})",
+            path.len()
+        );
+    }
+    let enckey = key.expand();
+    let mut blocks: [Block; PATH_BLOCKS] = Block::arr_from_slice(path);
+    blocks.iter_mut().for_each(|b| enckey.encrypt_mut(b));
+    xor_blocks_mut(&mut blocks);
+    Ok(blocks)
+}
+
+pub fn decrypt_path(key: &aria::Key, mut blocks: [Block; PATH_BLOCKS]) -> Result<String> {
+    let deckey: aria::DecryptKey = key.expand().into();
+    xor_blocks_mut(&mut blocks);
+    blocks.iter_mut().for_each(|b| deckey.decrypt_mut(b));
+    let path = blocks
+        .try_as_str()
+        .with_context(|| format!("invalid str in path: {blocks:?}"))?;
+    Ok(path.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_roundtrip() {
+        let key = aria::Key::from(*b"aBcDeFgH\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        let blocks = encrypt_path(&key, "Data/Item.scp").unwrap();
+        assert_eq!(decrypt_path(&key, blocks).unwrap(), "Data/Item.scp");
+
+        let path = "a".repeat(MAX_PATH_LEN);
+        let blocks = encrypt_path(&key, &path).unwrap();
+        assert_eq!(decrypt_path(&key, blocks).unwrap(), path);
+        assert!(encrypt_path(&key, &"a".repeat(MAX_PATH_LEN + 1)).is_err());
+    }
+}
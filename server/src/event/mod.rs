@@ -0,0 +1,269 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2023 Darek Stojaczyk
+
+use crate::executor;
+use crate::locked_vec::LockedVec;
+use crate::packet_stream::{IPCPacketStream, Service};
+use crate::registry::{BorrowRef, Borrowable};
+use async_proc::select;
+use clap::Args;
+use futures::{FutureExt, StreamExt};
+use log::{error, info, trace, warn};
+use packet::Payload;
+use store::{EventDef, EventStore};
+
+use std::fmt::Display;
+use std::net::TcpStream;
+use std::sync::Weak;
+use std::time::Duration;
+use std::{net::TcpListener, sync::Arc};
+
+use anyhow::Result;
+use smol::{Async, Task, Timer};
+
+pub mod store;
+
+/// EventMgr replacement
+#[derive(Args, Debug)]
+#[command(about, long_about, verbatim_doc_comment, disable_help_flag = true)]
+pub struct EventArgs {
+    /// Send the events from resources/events.json to the WorldSvr-s.
+    /// The event packets weren't observed in any capture of the original
+    /// EventMgr, so the WorldSvr-s might not understand them
+    #[arg(long)]
+    pub experimental_event_packets: bool,
+}
+
+pub struct Listener {
+    me: Weak<Listener>,
+    tcp_listener: Async<TcpListener>,
+    connections: LockedVec<Arc<BorrowRef<Connection, Service>>>,
+    /// Events that were already sent to all targeted connections
+    active_events: LockedVec<EventDef>,
+    args: Arc<crate::args::Config>,
+}
+
+impl Listener {
+    pub fn new(tcp_listener: Async<TcpListener>, args: &Arc<crate::args::Config>) -> Arc<Self> {
+        Arc::new_cyclic(|me| Self {
+            me: me.clone(),
+            tcp_listener,
+            connections: LockedVec::with_capacity(16),
+            active_events: LockedVec::new(),
+            args: args.clone(),
+        })
+    }
+
+    pub async fn listen(self: &mut Arc<Self>) -> Result<()> {
+        info!(
+            "Listener: started on {}",
+            self.tcp_listener.get_ref().local_addr()?
+        );
+        let keepalive = self.args.common.ipc_keepalive();
+        let event_args = self
+            .args
+            .services
+            .iter()
+            .find_map(|s| {
+                if let crate::args::Service::Event(args) = s {
+                    Some(args)
+                } else {
+                    None
+                }
+            })
+            .unwrap();
+
+        // stop scheduling together with the listener
+        let _scheduler = if event_args.experimental_event_packets {
+            let store = EventStore::load(EventStore::path(&self.args.common.resources_dir))?;
+            info!("Listener: loaded {} event(s)", store.events.len());
+            Some(self.start_scheduler(store))
+        } else {
+            info!("Listener: not sending any events, see --experimental-event-packets");
+            None
+        };
+
+        loop {
+            let (stream, _) = self.tcp_listener.accept().await?;
+
+            // Give the connection handler its own background task
+            let listener = self.me.upgrade().unwrap();
+            executor::spawn_local(async move {
+                let stream =
+                    match IPCPacketStream::from_host(Service::EventMgr, stream, keepalive).await {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            error!("Listener: {err}. Closing");
+                            return;
+                        }
+                    };
+                let id = stream.other_id;
+                let conn_ref = BorrowRef::new(id);
+
+                let mut conn = Connection {
+                    stream,
+                    listener: listener.clone(),
+                    conn_ref: conn_ref.clone(),
+                };
+
+                info!("Listener: {id} connected");
+                if let Err(err) = conn.handle().await {
+                    error!("Listener: {id} error: {err}");
+                } else {
+                    info!("Listener: closing {id}");
+                }
+                listener
+                    .connections
+                    .lock_write()
+                    .retain(|c| !Arc::ptr_eq(c, &conn_ref));
+            })
+            .detach();
+            // for now the tasks are just dropped, but we might want to
+            // wait for them in the future (or send a special shutdown
+            // message in each connection)
+        }
+    }
+
+    /// Send each event to the WorldSvr-s it targets once it starts, then
+    /// once it ends. The store is re-read whenever it changes, so events can
+    /// be also started or stopped at any time.
+    fn start_scheduler(&self, mut store: EventStore) -> Task<()> {
+        let listener = self.me.upgrade().unwrap();
+        executor::spawn_local(async move {
+            let mut interval_1s = Timer::interval(Duration::from_secs(1));
+            loop {
+                match store.reload_if_changed() {
+                    Ok(true) => info!("Listener: reloaded {} event(s)", store.events.len()),
+                    Ok(false) => {}
+                    Err(err) => error!("Listener: {err:#}. Keeping the previous events"),
+                }
+
+                let now = unix_time();
+                let active: Vec<EventDef> = store
+                    .events
+                    .iter()
+                    .filter(|e| e.is_active(now))
+                    .cloned()
+                    .collect();
+                // an event that was changed while active is restarted.
+                // The connections are noted under the same lock, so each
+                // one gets the events either from here, or from
+                // Connection::handle if it's registered later
+                let (stopped, started, connections) = {
+                    let mut prev_active = listener.active_events.lock_write();
+                    let connections = listener.connections.cloned();
+                    let stopped: Vec<EventDef> = prev_active
+                        .iter()
+                        .filter(|e| !active.contains(e))
+                        .cloned()
+                        .collect();
+                    let started: Vec<EventDef> = active
+                        .iter()
+                        .filter(|e| !prev_active.contains(e))
+                        .cloned()
+                        .collect();
+                    *prev_active = active;
+                    (stopped, started, connections)
+                };
+
+                for e in &stopped {
+                    info!("Listener: stopping event {}", e.id);
+                    send_to_targets(&connections, e, &e.stop_packet()).await;
+                }
+                for e in &started {
+                    info!("Listener: starting event {}", e.id);
+                    send_to_targets(&connections, e, &e.start_packet()).await;
+                }
+                interval_1s.next().await;
+            }
+        })
+    }
+}
+
+async fn send_to_targets(
+    connections: &[Arc<BorrowRef<Connection, Service>>],
+    event: &EventDef,
+    pkt: &impl Payload,
+) {
+    for conn_ref in connections {
+        if !event.is_targeted(&conn_ref.data) {
+            continue;
+        }
+        let Ok(mut conn) = conn_ref.borrow().await else {
+            // the connection is being closed
+            continue;
+        };
+        if let Err(err) = conn.stream.send(pkt).await {
+            warn!(
+                "Listener: failed to send event {} to {}: {err}",
+                event.id, conn_ref.data
+            );
+        }
+    }
+}
+
+fn unix_time() -> u32 {
+    std::time::UNIX_EPOCH.elapsed().unwrap().as_secs() as u32
+}
+
+pub struct Connection {
+    pub stream: IPCPacketStream<Async<TcpStream>>,
+    pub listener: Arc<Listener>,
+    pub conn_ref: Arc<BorrowRef<Connection, Service>>,
+}
+crate::impl_borrowable!(
+    Connection,
+    RefData = Service,
+    borrow_ref =.conn_ref
+);
+
+impl Display for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.stream.fmt(f)
+    }
+}
+
+impl Connection {
+    pub async fn handle(&mut self) -> Result<()> {
+        // Register the connection at the same time as the currently active
+        // events are noted, so each event is sent here exactly once:
+        // either below, or later by the scheduler
+        let active_events: Vec<EventDef> = {
+            let active_events = self.listener.active_events.lock_read();
+            self.listener.connections.push(self.conn_ref.clone());
+            active_events
+                .iter()
+                .filter(|e| e.is_targeted(&self.conn_ref.data))
+                .cloned()
+                .collect()
+        };
+
+        let other_conn = packet::pkt_common::Connect::from(self.stream.other_id);
+        self.stream
+            .send(&packet::pkt_event::ConnectAck {
+                unk1: 0x0,
+                unk2: [0x00, 0xff, 0x00, 0xff, 0xf5, 0x00, 0x00, 0x00, 0x00],
+                world_id: other_conn.server_id,
+                channel_id: other_conn.channel_id,
+                unk3: 0x0,
+                unk4: 0x1,
+            })
+            .await?;
+
+        for e in &active_events {
+            self.stream.send(&e.start_packet()).await?;
+        }
+
+        loop {
+            select! {
+                p = self.stream.recv().fuse() => {
+                    let p = p?;
+                    trace!("{self}: Got packet: {p:?}");
+                }
+                _ = self.conn_ref.borrower.wait_to_lend().fuse() => {
+                    self.lend_self().await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+//! Persistent event store, kept as `resources/events.json`. It's edited
+//! either by hand or with the `events` tool, and the running EventMgr
+//! picks up the changes on its own.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use packet::pkt_common::Connect;
+use packet::pkt_event::{EventStart, EventStop, EventType};
+use serde::{Deserialize, Serialize};
+
+use crate::packet_stream::Service;
+
+/// A single event, e.g.:
+/// ```json
+/// {
+///   "id": 1,
+///   "type": "Exp",
+///   "rate": 200,
+///   "start": 1735689600,
+///   "end": 1735696800,
+///   "targets": [{ "server": 1, "channel": 2 }]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventDef {
+    pub id: u32,
+    #[serde(rename = "type")]
+    pub event_type: EventType,
+    /// In percent, 100 = unchanged
+    pub rate: u32,
+    /// Unix timestamp
+    pub start: u32,
+    /// Unix timestamp
+    pub end: u32,
+    /// WorldSvr-s to send the event to. All of them if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<EventTarget>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventTarget {
+    pub server: u8,
+    /// All channels of the server if not specified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<u8>,
+}
+
+impl EventDef {
+    pub fn is_active(&self, now: u32) -> bool {
+        self.start <= now && now < self.end
+    }
+
+    pub fn is_targeted(&self, service: &Service) -> bool {
+        let Service::WorldSvr { .. } = service else {
+            return false;
+        };
+        let conn = Connect::from(*service);
+        self.targets.is_empty()
+            || self.targets.iter().any(|t| {
+                t.server == conn.server_id && t.channel.is_none_or(|c| c == conn.channel_id)
+            })
+    }
+
+    pub fn start_packet(&self) -> EventStart {
+        EventStart {
+            event_id: self.id,
+            event_type: self.event_type,
+            rate: self.rate,
+            start_time: self.start,
+            end_time: self.end,
+        }
+    }
+
+    pub fn stop_packet(&self) -> EventStop {
+        EventStop { event_id: self.id }
+    }
+}
+
+pub struct EventStore {
+    path: PathBuf,
+    /// Modification time of the file when it was last read or written
+    mtime: Option<SystemTime>,
+    pub events: Vec<EventDef>,
+}
+
+impl EventStore {
+    pub fn path(resources_dir: &Path) -> PathBuf {
+        resources_dir.join("resources/events.json")
+    }
+
+    /// Load all events from the given file. A missing file means no events.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let mut store = Self {
+            path,
+            mtime: None,
+            events: Vec::new(),
+        };
+        store.reload()?;
+        Ok(store)
+    }
+
+    /// Reload the events if the file was modified in the meantime.
+    /// Returns true if it was reloaded.
+    pub fn reload_if_changed(&mut self) -> Result<bool> {
+        if self.file_mtime() == self.mtime {
+            return Ok(false);
+        }
+        self.reload()?;
+        Ok(true)
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        // note the mtime first, so a failed reload isn't retried until the
+        // file changes again
+        self.mtime = self.file_mtime();
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.events.clear();
+                return Ok(());
+            }
+            Err(e) => return Err(e).with_context(|| format!("cannot read {:?}", self.path)),
+        };
+        let events: Vec<EventDef> = serde_json::from_slice(&data)
+            .with_context(|| format!("cannot parse {:?}", self.path))?;
+        validate(&events).with_context(|| format!("{:?}", self.path))?;
+        self.events = events;
+        Ok(())
+    }
+
+    /// Write all events back to the file. The file is replaced at once, so
+    /// a running EventMgr never sees it half-written.
+    pub fn save(&mut self) -> Result<()> {
+        validate(&self.events)?;
+        let data = serde_json::to_vec_pretty(&self.events)?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, data).with_context(|| format!("cannot write {tmp_path:?}"))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("cannot write {:?}", self.path))?;
+        self.mtime = self.file_mtime();
+        Ok(())
+    }
+
+    pub fn next_id(&self) -> Result<u32> {
+        let Some(max_id) = self.events.iter().map(|e| e.id).max() else {
+            return Ok(1);
+        };
+        let Some(id) = max_id.checked_add(1) else {
+            bail!("no event ids left after {max_id}");
+        };
+        Ok(id)
+    }
+
+    fn file_mtime(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok()
+    }
+}
+
+fn validate(events: &[EventDef]) -> Result<()> {
+    let mut ids = HashSet::new();
+    for e in events {
+        if !ids.insert(e.id) {
+            bail!("duplicate event id {}", e.id);
+        }
+        if e.start >= e.end {
+            bail!("event {} ends before it starts", e.id);
+        }
+    }
+    Ok(())
+}
@@ -98,7 +98,7 @@ impl GlobalLoginHandler {
                             let listener = self.listener.clone();
                             self.lend_self_until(async {
                                 super::handle_route_packet(&listener, p).await
-                            }).await?;
+                            }).await;
                         }
                         Packet::SetLoginInstance(p) => {
                             self.handle_login_stt(p).await.unwrap();
@@ -7,18 +7,19 @@ use crate::packet_stream::{IPCPacketStream, Service};
 use crate::registry::BorrowRef;
 use borrow_mutex::BorrowGuardArmed;
 use clap::Args;
-use log::{error, info};
+use log::{error, info, warn};
 use packet::pkt_common::ServiceID;
 use packet::*;
 use pkt_common::Connect;
-use pkt_global::CustomIdPacket;
+use pkt_global::{CustomIdPacket, RoutePacket};
 
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Weak;
 use std::time::Duration;
 use std::{net::TcpListener, sync::Arc};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{bail, Result};
 use smol::{Async, Timer};
 
 mod chat;
@@ -44,6 +45,17 @@ pub struct Listener {
     worlds: LockedVec<Arc<BorrowRef<GlobalWorldHandler, pkt_common::Connect>>>,
     db: Arc<BorrowRef<GlobalDbHandler, ()>>,
     login: Arc<BorrowRef<GlobalLoginHandler, ()>>,
+    pub route_stats: RouteStats,
+}
+
+/// Counters of the RoutePacket-s received from WorldSvr-s and LoginSvr.
+/// They're logged once a minute
+#[derive(Debug, Default)]
+pub struct RouteStats {
+    /// Forwarded to the destination
+    pub routed: AtomicU64,
+    /// Couldn't be forwarded, so they were dropped
+    pub failed: AtomicU64,
 }
 
 impl std::fmt::Display for Listener {
@@ -64,6 +76,7 @@ impl Listener {
             worlds: LockedVec::new(),
             db: BorrowRef::new(()),
             login: BorrowRef::new(()),
+            route_stats: RouteStats::default(),
         })
     }
 
@@ -86,6 +99,9 @@ impl Listener {
             .unwrap();
 
         self.connect_to_globaldb();
+        let keepalive = self.args.common.ipc_keepalive();
+        // stop logging together with the listener
+        let _route_stats = executor::spawn_local(log_route_stats(self.me.upgrade().unwrap()));
 
         loop {
             let (stream, _) = self.tcp_listener.accept().await.unwrap();
@@ -94,9 +110,19 @@ impl Listener {
             executor::spawn_local(async move {
                 info!("Listener: new connection ...");
 
-                let stream = IPCPacketStream::from_host(Service::GlobalMgrSvr { id: 0x80 }, stream)
-                    .await
-                    .unwrap();
+                let stream = match IPCPacketStream::from_host(
+                    Service::GlobalMgrSvr { id: 0x80 },
+                    stream,
+                    keepalive,
+                )
+                .await
+                {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        error!("Listener: {err}. Closing");
+                        return;
+                    }
+                };
                 let id = stream.other_id;
 
                 info!("Listener: {id} connected");
@@ -155,6 +181,7 @@ impl Listener {
     fn connect_to_globaldb(&self) {
         let listener = self.me.upgrade().unwrap();
         let conn_ref = self.db.clone();
+        let keepalive = self.args.common.ipc_keepalive();
 
         // Give the connection handler its own background task
         executor::spawn_local(async move {
@@ -170,6 +197,7 @@ impl Listener {
                     Service::GlobalMgrSvr { id: 0x80 },
                     Service::DBAgent,
                     db_stream,
+                    keepalive,
                 )
                 .await
                 .unwrap();
@@ -184,42 +212,75 @@ impl Listener {
     }
 }
 
-pub async fn handle_route_packet(
-    listener: &Arc<Listener>,
-    p: pkt_global::RoutePacket,
-) -> Result<()> {
+/// Forward a RoutePacket to the service it's addressed to. If that's not
+/// possible, the packet is dropped. The sender is never disconnected because
+/// of it.
+pub async fn handle_route_packet(listener: &Arc<Listener>, p: RoutePacket) {
     let route_hdr = p.droute_hdr.route_hdr.clone();
 
+    let ret = route_to(
+        listener,
+        route_hdr.server_id,
+        route_hdr.channel_id,
+        &CustomIdPacket {
+            id: route_hdr.origin_main_cmd,
+            data: p,
+        },
+    )
+    .await;
+    if let Err(err) = ret {
+        // The original GMS might answer the origin somehow, but it's not
+        // known how
+        let failed = listener.route_stats.failed.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!("{listener}: Can't route to {route_hdr:?}: {err}. Dropping it ({failed} failed routes so far)");
+        return;
+    }
+    listener.route_stats.routed.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Log the [`RouteStats`] once a minute, if they changed
+async fn log_route_stats(listener: Arc<Listener>) {
+    let mut last = (0, 0);
+    loop {
+        Timer::after(Duration::from_secs(60)).await;
+        let stats = &listener.route_stats;
+        let cur = (
+            stats.routed.load(Ordering::Relaxed),
+            stats.failed.load(Ordering::Relaxed),
+        );
+        if cur != last {
+            info!("{listener}: {} packets routed, {} failed", cur.0, cur.1);
+            last = cur;
+        }
+    }
+}
+
+async fn route_to(
+    listener: &Arc<Listener>,
+    server_id: u8,
+    channel_id: u8,
+    pkt: &impl Payload,
+) -> Result<()> {
     let login_route = Connect::from(Service::LoginSvr);
     let worlds = listener.worlds.cloned();
 
     let mut target_stream = {
-        if route_hdr.channel_id == login_route.channel_id
-            && route_hdr.server_id == login_route.server_id
-        {
+        if channel_id == login_route.channel_id && server_id == login_route.server_id {
             let login_ref = &listener.login;
-            BorrowGuardArmed::map(login_ref.borrow().await.unwrap(), |m| &mut m.stream)
+            BorrowGuardArmed::map(login_ref.borrow().await?, |m| &mut m.stream)
         } else {
             let world_ref = worlds.iter().find(|conn_ref| {
                 let s = &conn_ref.data;
-                s.server_id == route_hdr.server_id && s.channel_id == route_hdr.channel_id
+                s.server_id == server_id && s.channel_id == channel_id
             });
 
             let Some(world_ref) = world_ref else {
-                bail!("Can't find a conn to route to: {route_hdr:?}");
+                bail!("No such connection");
             };
 
-            BorrowGuardArmed::map(world_ref.borrow().await.unwrap(), |m| &mut m.stream)
+            BorrowGuardArmed::map(world_ref.borrow().await?, |m| &mut m.stream)
         }
     };
 
-    target_stream
-        .send(&CustomIdPacket {
-            id: route_hdr.origin_main_cmd,
-            data: p,
-        })
-        .await
-        .map_err(|e| anyhow!("Failed to forward RoutePacket to {route_hdr:?}: {e}",))?;
-
-    Ok(())
+    target_stream.send(pkt).await
 }
@@ -122,7 +122,7 @@ impl GlobalWorldHandler {
                             let listener = self.listener.clone();
                             self.lend_self_until(async {
                                 super::handle_route_packet(&listener, p).await
-                            }).await?;
+                            }).await;
                         }
                         Packet::SubPasswordCheckRequest(p) => {
                             self.handle_sub_password_check(p).await.unwrap();
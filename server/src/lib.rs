@@ -4,9 +4,14 @@
 pub mod arc_slab;
 pub mod args;
 pub mod locked_vec;
+pub mod packet_log;
 pub mod packet_stream;
 pub mod registry;
+pub mod replay;
+pub mod tools;
 
+#[cfg(feature = "chat")]
+pub mod chat;
 #[cfg(feature = "crypto")]
 pub mod crypto;
 #[cfg(feature = "event")]
@@ -10,8 +10,8 @@ use log::warn;
 use packet::pkt_common::*;
 use packet::*;
 use pkt_global::{
-    CustomIdPacket, DuplexRouteHeader, LoginServerNode, LoginServerState, NotifyUserCount,
-    RouteHeader, RoutePacket, VerifyLinksResult,
+    CustomIdPacket, DuplexRouteHeader, LoginServerNode, NotifyUserCount, RouteHeader, RoutePacket,
+    VerifyLinksResult,
 };
 use smol::{Async, Timer};
 
@@ -87,9 +87,7 @@ impl GmsHandler {
 
     pub async fn handle_packet(&mut self, p: Packet) -> Result<()> {
         match p {
-            Packet::ServerState(p) => {
-                let s = LoginServerState::deserialize_no_hdr(&p.bytes)
-                    .map_err(|_| anyhow!("{self}: Malformed LoginServerState packet"))?;
+            Packet::LoginServerState(s) => {
                 self.update_world_server_list(s.servers.0);
             }
             Packet::MultipleLoginDisconnectResponse(_) => {
@@ -2,13 +2,17 @@
 // Copyright(c) 2024 Darek Stojaczyk
 
 use crate::executor;
-use crate::packet_stream::{IPCPacketStream, PacketStream, Service, StreamConfig};
+use crate::packet_stream::{
+    ChecksumMode, IPCPacketStream, PacketDecoder, PacketStream, SendQueueConfig, Service,
+    StreamConfig,
+};
 use crate::registry::{BorrowRef, BorrowRegistry};
 use clap::Args;
 use db::GlobalDbHandler;
 use gms::GmsHandler;
-use log::{error, info};
-use packet::Packet;
+use log::{error, info, warn};
+use packet::pkt_common::ServiceID;
+use packet::{Packet, PacketContext};
 use user::UserConnHandler;
 
 use std::collections::hash_map::Entry;
@@ -29,7 +33,13 @@ mod user;
 /// LoginSvr replacement
 #[derive(Args, Debug, Default)]
 #[command(about, long_about, verbatim_doc_comment, disable_help_flag = true)]
-pub struct LoginArgs {}
+pub struct LoginArgs {
+    /// Use the same XOR table seed and key index for every client
+    /// connection, instead of random ones. This makes the client traffic
+    /// easy to decode offline, so use it only for debugging.
+    #[clap(long)]
+    pub fixed_xor_seed: bool,
+}
 
 pub struct Listener {
     me: Weak<Listener>,
@@ -68,7 +78,7 @@ impl Listener {
             "Listener: started on {}",
             self.tcp_listener.get_ref().local_addr()?
         );
-        let _loginargs = self
+        let login_args = self
             .args
             .services
             .iter()
@@ -81,6 +91,17 @@ impl Listener {
             })
             .unwrap();
 
+        let (xor_table_seed, xor_key_idx) = match login_args.fixed_xor_seed {
+            true => {
+                warn!("Listener: using a fixed XOR seed for all connections");
+                (
+                    Some(PacketDecoder::FIXED_XOR_TABLE_SEED),
+                    Some(PacketDecoder::FIXED_XOR_KEY_IDX),
+                )
+            }
+            false => (None, None),
+        };
+
         self.connect_to_globaldb();
         self.connect_to_gms();
 
@@ -92,7 +113,7 @@ impl Listener {
                 info!("Listener: new user connection ...");
 
                 let id = stream.as_fd().as_raw_fd();
-                let stream = PacketStream::new(
+                let mut stream = PacketStream::new(
                     stream,
                     StreamConfig {
                         self_name: "LoginSvr".into(),
@@ -101,8 +122,25 @@ impl Listener {
                         deserialize_checksum: true,
                         encode_tx: true,
                         decode_rx: true,
+                        client_side: false,
+                        xor_table_seed,
+                        xor_key_idx,
+                        checksum_mode: ChecksumMode::Strict,
+                        rx_context: PacketContext::new(None, Some(ServiceID::LoginSvr)),
+                        // all client packets are much smaller
+                        max_pkt_len: 0x1000,
+                        recv_timeout: None,
                     },
                 );
+                // the client only gets a few packets at a time
+                let send_queue = SendQueueConfig {
+                    capacity: 64,
+                    ..Default::default()
+                };
+                if let Err(err) = stream.start_send_queue(send_queue) {
+                    error!("Listener: {id} error: {err}");
+                    return;
+                }
 
                 info!("Listener: {id} connected");
                 if let Err(err) = listener.handle_new_conn(stream).await {
@@ -154,6 +192,7 @@ impl Listener {
 
     fn connect_to_globaldb(&self) {
         let listener = self.me.upgrade().unwrap();
+        let keepalive = self.args.common.ipc_keepalive();
 
         executor::spawn_local(async move {
             loop {
@@ -163,10 +202,14 @@ impl Listener {
                 };
 
                 info!("Listener: DB connection established");
-                let stream =
-                    IPCPacketStream::from_conn(Service::LoginSvr, Service::DBAgent, stream)
-                        .await
-                        .unwrap();
+                let stream = IPCPacketStream::from_conn(
+                    Service::LoginSvr,
+                    Service::DBAgent,
+                    stream,
+                    keepalive,
+                )
+                .await
+                .unwrap();
 
                 let conn_ref = listener.globaldb.clone();
                 let ret = GlobalDbHandler::new(listener.clone(), stream, conn_ref)
@@ -180,6 +223,7 @@ impl Listener {
 
     fn connect_to_gms(&self) {
         let listener = self.me.upgrade().unwrap();
+        let keepalive = self.args.common.ipc_keepalive();
 
         executor::spawn_local(async move {
             loop {
@@ -193,6 +237,7 @@ impl Listener {
                     Service::LoginSvr,
                     Service::GlobalMgrSvr { id: 0 },
                     stream,
+                    keepalive,
                 )
                 .await
                 .unwrap();
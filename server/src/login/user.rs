@@ -284,11 +284,6 @@ impl UserConnHandler {
                 url_list.urls.push("http://localhost?v3=".into());
                 url_list.urls.push("".into());
 
-                let mut buf = Vec::with_capacity(512);
-                let len = url_list.serialize_no_hdr(&mut buf).unwrap();
-                url_list.urls_num_bytes2 = len.try_into().unwrap();
-                url_list.urls_num_bytes = url_list.urls_num_bytes2.checked_sub(2).unwrap();
-
                 self.stream.send(&url_list).await.unwrap();
             }
 
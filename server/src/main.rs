@@ -12,9 +12,30 @@ use std::{net::TcpListener, sync::Arc};
 fn main() {
     setup_log(false);
 
-    let args = Arc::new(server::args::parse());
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(tool) = server::tools::parse_from(&args) {
+        if let Err(e) = tool.run() {
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let args = Arc::new(server::args::parse_from(&args));
     assert!(!args.services.is_empty());
 
+    #[cfg(feature = "chat")]
+    if args
+        .services
+        .iter()
+        .any(|f| matches!(f, server::args::Service::Chat { .. }))
+    {
+        let sock = Async::<TcpListener>::bind(([127, 0, 0, 1], 38121)) //
+            .expect("Cannot bind to 38121");
+        let listener = server::chat::Listener::new(sock, &args);
+        executor::spawn_local(async move { listener.listen().await }).detach();
+    }
+
     #[cfg(feature = "event")]
     if args
         .services
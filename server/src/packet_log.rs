@@ -0,0 +1,216 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+//! Timestamped packet log.
+//!
+//! Each packet is stored in a single text line:
+//! ```text
+//! <msecs since start> <rx|tx> <packet id> <payload hex>
+//! ```
+//! e.g. `1503 rx 0x0005 a1010100`. Empty lines and lines starting
+//! with `#` are ignored, so recordings can be annotated by hand.
+//!
+//! The direction is always relative to the service being recorded.
+//! When the proxy records a conversation, the downstream port is the
+//! service, so everything received from upstream is `rx`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use packet::{Packet, Payload};
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Received by the service
+    Rx,
+    /// Sent by the service
+    Tx,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketLogEntry {
+    pub time: Duration,
+    pub direction: Direction,
+    pub id: u16,
+    pub payload: Vec<u8>,
+}
+
+/// Appends [`PacketLogEntry`]-s to a file (or any other writer).
+///
+/// This can be cloned and shared between multiple tasks. All entries
+/// are timestamped relative to the creation of the original writer.
+#[derive(Clone)]
+pub struct PacketLogWriter {
+    inner: Arc<Mutex<Box<dyn Write + Send>>>,
+    start: Instant,
+}
+
+impl PacketLogWriter {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Box::new(writer))),
+            start: Instant::now(),
+        }
+    }
+
+    /// Create a new log file. Fails if it already exists
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .with_context(|| format!("Can't create packet log {}", path.display()))?;
+        let mut log = Self::new(BufWriter::new(file));
+        log.write_line("# <msecs> <rx|tx> <id> <payload>")?;
+        Ok(log)
+    }
+
+    /// Record the serialized payload of a packet.
+    pub fn record(&mut self, direction: Direction, pkt: &Packet) -> Result<()> {
+        let mut payload = Vec::new();
+        pkt.serialize_no_hdr(&mut payload)?;
+        let entry = PacketLogEntry {
+            time: self.start.elapsed(),
+            direction,
+            id: pkt.id(),
+            payload,
+        };
+        self.write_line(&entry.to_string())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        writeln!(inner, "{line}")?;
+        // keep the log usable even if the process is killed
+        inner.flush()?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for PacketLogWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PacketLogWriter")
+            .field("start", &self.start)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Read all entries from a packet log.
+pub fn read(reader: impl BufRead) -> Result<Vec<PacketLogEntry>> {
+    let mut entries = Vec::new();
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = line
+            .parse()
+            .with_context(|| format!("Malformed packet log line {}", lineno + 1))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+pub fn read_file(path: &Path) -> Result<Vec<PacketLogEntry>> {
+    let file =
+        File::open(path).with_context(|| format!("Can't open packet log {}", path.display()))?;
+    read(BufReader::new(file))
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rx => write!(f, "rx"),
+            Self::Tx => write!(f, "tx"),
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rx" => Ok(Self::Rx),
+            "tx" => Ok(Self::Tx),
+            _ => bail!("Invalid direction `{s}`"),
+        }
+    }
+}
+
+impl Display for PacketLogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {:#06x} {}",
+            self.time.as_millis(),
+            self.direction,
+            self.id,
+            hex::encode(&self.payload)
+        )
+    }
+}
+
+impl FromStr for PacketLogEntry {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        let mut next = |name: &str| parts.next().ok_or_else(|| anyhow!("Missing {name}"));
+
+        let time = Duration::from_millis(next("timestamp")?.parse()?);
+        let direction = next("direction")?.parse()?;
+        let id = next("packet id")?;
+        let id = u16::from_str_radix(id.trim_start_matches("0x"), 16)
+            .with_context(|| format!("Invalid packet id `{id}`"))?;
+        // packets without payload have nothing more in the line
+        let payload = match parts.next() {
+            Some(payload) => hex::decode(payload).context("Invalid payload")?,
+            None => Vec::new(),
+        };
+        if parts.next().is_some() {
+            bail!("Trailing data");
+        }
+
+        Ok(Self {
+            time,
+            direction,
+            id,
+            payload,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_roundtrip() {
+        let entry = PacketLogEntry {
+            time: Duration::from_millis(1503),
+            direction: Direction::Rx,
+            id: 0x5,
+            payload: vec![0xa1, 0x01, 0x01, 0x00],
+        };
+        assert_eq!(entry.to_string(), "1503 rx 0x0005 a1010100");
+
+        let log = format!("# comment\n\n{entry}\n0 tx 0x2b3\n");
+        let entries = read(log.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], entry);
+        assert_eq!(entries[1].direction, Direction::Tx);
+        assert_eq!(entries[1].id, 0x2b3);
+        assert!(entries[1].payload.is_empty());
+
+        assert!(read("0 xx 0x0005".as_bytes()).is_err());
+        assert!(read("0 rx 0x0005 abc".as_bytes()).is_err());
+    }
+}
@@ -2,15 +2,22 @@
 // Copyright(c) 2023 Darek Stojaczyk
 
 use futures::{AsyncRead, AsyncWrite};
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
 use packet::*;
 
 use anyhow::{anyhow, bail, Result};
 use pkt_common::{Connect, ServiceID};
+use smol::channel::{Receiver, Sender, TrySendError};
 use smol::io::{AsyncReadExt, AsyncWriteExt};
+use smol::{Async, Timer};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
 use std::{fmt::Display, io::ErrorKind};
 use thiserror::Error;
 
+use crate::executor;
+
 /// A wrapper that reads / writes complete [`Payload`] packets
 /// to the underlying reader / writer.
 ///
@@ -19,12 +26,18 @@ use thiserror::Error;
 pub struct PacketStream<T: Unpin> {
     pub stream: T,
     send_buf: Vec<u8>,
+    /// If set, packets are written by a background task instead
+    send_queue: Option<SendQueue>,
     recv_buf: AsyncBufReader,
     /// packet length that was parsed in a packet header,
     /// but whose payload is still being received
     recv_pkt_len: Option<u16>,
+    /// When the last packet was received, or when the stream was created
+    last_recv: Instant,
     pub config: StreamConfig,
     pub decoder: Option<Box<PacketDecoder>>,
+    /// Used instead of `decoder` on the client side
+    pub encoder: Option<Box<PacketEncoder>>,
 }
 
 #[derive(Debug)]
@@ -35,6 +48,112 @@ pub struct StreamConfig {
     pub deserialize_checksum: bool,
     pub encode_tx: bool,
     pub decode_rx: bool,
+    /// This is the game client's end of the connection, so `encode_tx`
+    /// and `decode_rx` work the other way around. See [`PacketEncoder`]
+    pub client_side: bool,
+    /// Seed of the XOR table used with `decode_rx` / `encode_tx`.
+    /// Random if not specified. Unused on the client side, where it's
+    /// received from the server instead
+    pub xor_table_seed: Option<u32>,
+    /// Initial XOR key index used with `decode_rx`, or with `encode_tx`
+    /// on the client side. Random if not specified
+    pub xor_key_idx: Option<u16>,
+    /// What to do with received packets whose checksum doesn't match.
+    /// Only relevant with `decode_rx` and `deserialize_checksum`
+    pub checksum_mode: ChecksumMode,
+    /// Used to tell apart received packets with the same ID
+    pub rx_context: PacketContext,
+    /// Received packets longer than this (including the header) are
+    /// rejected before the receive buffer is grown to fit them
+    pub max_pkt_len: u16,
+    /// Fail [`PacketStream::recv`] with [`RecvError::Timeout`] once no
+    /// packet was received for this long
+    pub recv_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumMode {
+    /// Fail to receive the packet, which usually terminates the connection
+    #[default]
+    Strict,
+    /// Log a warning and process the packet anyway
+    Lenient,
+}
+
+/// See [`PacketStream::start_send_queue`]
+#[derive(Debug, Clone, Copy)]
+pub struct SendQueueConfig {
+    /// Max number of packets waiting to be written
+    pub capacity: usize,
+    /// What to do with packets that don't fit in the queue
+    pub overflow: OverflowPolicy,
+    /// Send a [`Keepalive`](pkt_event::Keepalive) whenever nothing else
+    /// was sent for this long. Not supported with `encode_tx`
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for SendQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            overflow: OverflowPolicy::Disconnect,
+            keepalive: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until there's room in the queue
+    Wait,
+    /// Fail to send the packet and close the queue. The remaining packets
+    /// are still written, then the connection is shut down
+    #[default]
+    Disconnect,
+    /// Drop the packet with a warning
+    Drop,
+}
+
+#[derive(Debug)]
+struct SendQueue {
+    tx: Sender<Vec<u8>>,
+    config: SendQueueConfig,
+}
+
+/// A stream that can be written through another, independent handle, so
+/// that [`PacketStream::start_send_queue`] can write from a background task
+/// while the stream itself is used for receiving.
+pub trait CloneWriter {
+    type Writer: AsyncWrite + Unpin + 'static;
+
+    fn clone_writer(&self) -> std::io::Result<Self::Writer>;
+
+    /// Shut down the entire connection, for both reading and writing
+    fn shutdown(writer: &Self::Writer) -> std::io::Result<()>;
+}
+
+impl CloneWriter for Async<TcpStream> {
+    type Writer = Async<TcpStream>;
+
+    fn clone_writer(&self) -> std::io::Result<Self::Writer> {
+        Async::new(self.get_ref().try_clone()?)
+    }
+
+    fn shutdown(writer: &Self::Writer) -> std::io::Result<()> {
+        writer.get_ref().shutdown(std::net::Shutdown::Both)
+    }
+}
+
+impl CloneWriter for Async<UnixStream> {
+    type Writer = Async<UnixStream>;
+
+    fn clone_writer(&self) -> std::io::Result<Self::Writer> {
+        Async::new(self.get_ref().try_clone()?)
+    }
+
+    fn shutdown(writer: &Self::Writer) -> std::io::Result<()> {
+        writer.get_ref().shutdown(std::net::Shutdown::Both)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -47,22 +166,46 @@ pub enum RecvError {
     Deserialize(#[from] PayloadDeserializeError),
     #[error("Decode: {0}")]
     Decode(#[from] PacketDecodeError),
+    #[error("Packet too long ({len:#x} bytes, at most {max:#x} allowed)")]
+    TooLong { len: u16, max: u16 },
+    #[error("Invalid payload length of packet {id:#x} ({len:#x} bytes, expected {expected:#x})")]
+    InvalidLength {
+        id: u16,
+        len: usize,
+        expected: usize,
+    },
+    #[error("No packets received for {0:?}")]
+    Timeout(Duration),
 }
 
 impl<T: Unpin> PacketStream<T> {
     pub fn new(stream: T, config: StreamConfig) -> Self {
-        let decoder = match config.decode_rx {
-            true => Some(Box::new(PacketDecoder::new(Some(0x46631ab5), Some(0x1BB8)))),
-            false => None,
-        };
+        let mut decoder = None;
+        let mut encoder = None;
+        if config.decode_rx || config.encode_tx {
+            if config.client_side {
+                let mut e = PacketEncoder::new(config.xor_key_idx);
+                e.rx_checksum = config.deserialize_checksum;
+                e.tx_checksum = config.serialize_checksum;
+                encoder = Some(Box::new(e));
+            } else {
+                let mut d = PacketDecoder::new(config.xor_table_seed, config.xor_key_idx);
+                d.rx_checksum = config.deserialize_checksum;
+                d.tx_checksum = config.serialize_checksum;
+                decoder = Some(Box::new(d));
+            }
+        }
 
         Self {
             stream,
             send_buf: Vec::new(),
+            send_queue: None,
             recv_buf: AsyncBufReader::new(),
             recv_pkt_len: None,
+            last_recv: Instant::now(),
             config,
             decoder,
+            encoder,
         }
     }
 }
@@ -71,6 +214,7 @@ impl<T: Unpin + AsyncRead> PacketStream<T> {
     /// Try to receive a packet from the stream.
     /// This is cancellation-safe.
     pub async fn recv(&mut self) -> std::result::Result<Packet, RecvError> {
+        let deadline = self.config.recv_timeout.map(|t| (self.last_recv + t, t));
         let pkt_len = if let Some(pkt_len) = &self.recv_pkt_len {
             *pkt_len
         } else {
@@ -79,14 +223,13 @@ impl<T: Unpin + AsyncRead> PacketStream<T> {
             // if the packet has no payload => nothing in the buffer is
             // then mutated
             let buf_len = 4;
-            let hdr_buf = self
-                .recv_buf
-                .fill_buf_mut(buf_len, &mut self.stream)
-                .await
-                .map_err(|_| RecvError::Terminated)?;
-
-            let pkt_len = if let Some(decoder) = &mut self.decoder {
-                match decoder.decode(&mut hdr_buf[..4])? {
+            let hdr_buf =
+                fill_buf_until(&mut self.recv_buf, buf_len, &mut self.stream, deadline).await?;
+
+            let codec =
+                xor_codec(&mut self.decoder, &mut self.encoder).filter(|_| self.config.decode_rx);
+            let pkt_len = if let Some(codec) = codec {
+                match codec.decode(&mut hdr_buf[..4])? {
                     PacketDecodeResult::PayloadIncomplete(len) => len,
                     _ => unreachable!(),
                 }
@@ -94,28 +237,61 @@ impl<T: Unpin + AsyncRead> PacketStream<T> {
                 u16::from_le_bytes(hdr_buf[2..4].try_into().unwrap())
             };
 
+            let hdr_len = Header::num_bytes(self.config.deserialize_checksum);
+            if (pkt_len as usize) < hdr_len {
+                return Err(HeaderDeserializeError::TooSmall {
+                    expected: hdr_len as _,
+                    found: pkt_len,
+                }
+                .into());
+            }
+            if pkt_len > self.config.max_pkt_len {
+                return Err(RecvError::TooLong {
+                    len: pkt_len,
+                    max: self.config.max_pkt_len,
+                });
+            }
+
             *self.recv_pkt_len.insert(pkt_len)
         };
 
-        let pkt_buf = self
-            .recv_buf
-            .fill_buf_mut(pkt_len as _, &mut self.stream)
-            .await
-            .map_err(|_| RecvError::Terminated)?;
+        let pkt_buf =
+            fill_buf_until(&mut self.recv_buf, pkt_len as _, &mut self.stream, deadline).await?;
 
-        if let Some(decoder) = &mut self.decoder {
-            match decoder.decode(pkt_buf)? {
-                PacketDecodeResult::Done(len) => {
+        let codec =
+            xor_codec(&mut self.decoder, &mut self.encoder).filter(|_| self.config.decode_rx);
+        if let Some(codec) = codec {
+            match codec.decode(pkt_buf) {
+                Ok(PacketDecodeResult::Done(len)) => {
                     debug_assert_eq!(len, pkt_len);
                 }
-                _ => unreachable!(),
+                Ok(_) => unreachable!(),
+                Err(e @ PacketDecodeError::Checksum { .. })
+                    if self.config.checksum_mode == ChecksumMode::Lenient =>
+                {
+                    // the packet is fully decoded regardless
+                    warn!(
+                        "{self_name}<-{other_name}: {e}",
+                        self_name = self.config.self_name,
+                        other_name = self.config.other_name
+                    );
+                }
+                Err(e) => return Err(e.into()),
             }
         }
 
         let hdr_len = Header::num_bytes(self.config.deserialize_checksum);
         let hdr = Header::deserialize(&pkt_buf[..hdr_len], self.config.deserialize_checksum)?;
         let payload_buf = &pkt_buf[hdr_len..];
-        let p = Packet::deserialize_no_hdr(hdr.id, payload_buf);
+        let ctx = &self.config.rx_context;
+        let p = match Packet::payload_size(ctx, hdr.id) {
+            Some(expected) if expected != payload_buf.len() => Err(RecvError::InvalidLength {
+                id: hdr.id,
+                len: payload_buf.len(),
+                expected,
+            }),
+            _ => Packet::deserialize_no_hdr_ctx(ctx, hdr.id, payload_buf).map_err(Into::into),
+        };
         if let Err(e) = &p {
             error!("{self_name}<-{other_name}: Can't decode packet {hdr:x?}: {e}\nPayload: {payload_buf:x?}",
                 self_name = self.config.self_name,
@@ -124,8 +300,12 @@ impl<T: Unpin + AsyncRead> PacketStream<T> {
 
         self.recv_pkt_len = None;
         self.recv_buf.consume(pkt_len as _);
+        self.last_recv = Instant::now();
 
         let p = p?;
+        if let (Some(encoder), Packet::S2CConnect(p)) = (&mut self.encoder, &p) {
+            encoder.set_xor_seed(p.xor_seed_2, p.xor_key_idx);
+        }
         debug!(
             "{self_name}<-{other_name}: recv: {p:?}",
             self_name = self.config.self_name,
@@ -135,11 +315,114 @@ impl<T: Unpin + AsyncRead> PacketStream<T> {
     }
 }
 
+/// [`AsyncBufReader::fill_buf_mut`] that fails with [`RecvError::Timeout`]
+/// once the deadline passes
+async fn fill_buf_until<'a, T: AsyncRead + Unpin>(
+    buf: &'a mut AsyncBufReader,
+    len: usize,
+    reader: &mut T,
+    deadline: Option<(Instant, Duration)>,
+) -> std::result::Result<&'a mut [u8], RecvError> {
+    let fill = async {
+        buf.fill_buf_mut(len, reader)
+            .await
+            .map_err(|_| RecvError::Terminated)
+    };
+    let Some((deadline, timeout)) = deadline else {
+        return fill.await;
+    };
+    let timer = async {
+        Timer::at(deadline).await;
+        Err(RecvError::Timeout(timeout))
+    };
+    smol::future::or(fill, timer).await
+}
+
+impl<T: Unpin + CloneWriter + 'static> PacketStream<T> {
+    /// Write all further packets from a background task, through a queue
+    /// of up to `config.capacity` packets. This makes [`Self::send`] return
+    /// as soon as the packet is queued, so a slow peer doesn't stall the
+    /// sender.
+    ///
+    /// The queued packets are still written after the stream is dropped,
+    /// and only then the connection is shut down.
+    ///
+    /// With [`StreamConfig::encode_tx`] only [`OverflowPolicy::Disconnect`]
+    /// is accepted. Packets are encoded before they're queued, so a dropped
+    /// packet, or a send cancelled while waiting for room, would leave the
+    /// other side unable to decode any further packets.
+    pub fn start_send_queue(&mut self, config: SendQueueConfig) -> std::io::Result<()> {
+        if self.config.encode_tx && config.overflow != OverflowPolicy::Disconnect {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "XOR encoded packets can't be dropped or waited for",
+            ));
+        }
+        let keepalive = match config.keepalive {
+            Some(_) if self.config.encode_tx => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "Keepalives can't be XOR encoded",
+                ));
+            }
+            Some(interval) => {
+                let mut buf = Vec::new();
+                pkt_event::Keepalive {}
+                    .serialize(&mut buf, self.config.serialize_checksum)
+                    .unwrap();
+                Some((interval, buf))
+            }
+            None => None,
+        };
+        let writer = self.stream.clone_writer()?;
+        let (tx, rx) = smol::channel::bounded(config.capacity);
+        let name = format!("{}->{}", self.config.self_name, self.config.other_name);
+        executor::spawn_local(write_send_queue::<T>(writer, rx, keepalive, name)).detach();
+        self.send_queue = Some(SendQueue { tx, config });
+        Ok(())
+    }
+}
+
+async fn write_send_queue<T: CloneWriter>(
+    mut writer: T::Writer,
+    rx: Receiver<Vec<u8>>,
+    keepalive: Option<(Duration, Vec<u8>)>,
+    name: String,
+) {
+    loop {
+        let next = async { rx.recv().await.ok() };
+        let buf = match &keepalive {
+            Some((interval, keepalive)) => {
+                let idle = async {
+                    Timer::after(*interval).await;
+                    Some(keepalive.clone())
+                };
+                smol::future::or(next, idle).await
+            }
+            None => next.await,
+        };
+        let Some(buf) = buf else {
+            break;
+        };
+        if let Err(e) = writer.write_all(&buf).await {
+            error!("{name}: Failed to send: {e}");
+            break;
+        }
+    }
+    // The stream was dropped, the queue overflowed, or the connection is
+    // already broken. Either way, fail any further sends and disconnect
+    rx.close();
+    let _ = T::shutdown(&writer);
+}
+
 impl<T: Unpin + AsyncWrite> PacketStream<T> {
     /// Send a packet.
-    /// This is cancellation-safe, although the packet might
-    /// be send incompletely, and further attempts to send more
-    /// packets will immediately fail.
+    ///
+    /// With a send queue (see [`Self::start_send_queue`]) this only waits
+    /// for the packet to be queued, which is cancellation-safe.
+    /// Otherwise the packet is written directly. That's cancellation-safe
+    /// as well, although the packet might be sent incompletely, and
+    /// further attempts to send more packets will immediately fail.
     pub async fn send(&mut self, pkt: &impl Payload) -> Result<()> {
         if !self.send_buf.is_empty() {
             bail!("One of the previous send operations was cancelled. Aborting");
@@ -149,14 +432,66 @@ impl<T: Unpin + AsyncWrite> PacketStream<T> {
             self_name = self.config.self_name,
             other_name = self.config.other_name
         );
-        let len = pkt.serialize(&mut self.send_buf, self.config.serialize_checksum)?;
-        if let Some(decoder) = &mut self.decoder {
-            decoder.encode(&mut self.send_buf[..len]);
+        let len = match pkt.serialize(&mut self.send_buf, self.config.serialize_checksum) {
+            Ok(len) => len,
+            Err(e) => {
+                self.send_buf.clear();
+                return Err(e.into());
+            }
+        };
+        let codec =
+            xor_codec(&mut self.decoder, &mut self.encoder).filter(|_| self.config.encode_tx);
+        if let Some(codec) = codec {
+            if let Err(e) = codec.encode(&mut self.send_buf[..len]) {
+                // nothing was sent, so further packets can be still sent
+                self.send_buf.clear();
+                return Err(e.into());
+            }
+        }
+
+        if let Some(queue) = &self.send_queue {
+            let buf = std::mem::take(&mut self.send_buf);
+            return self.push_send_queue(queue, buf).await;
         }
+
         self.stream.write_all(&self.send_buf[..len]).await?;
         self.send_buf.clear();
         Ok(())
     }
+
+    async fn push_send_queue(&self, queue: &SendQueue, buf: Vec<u8>) -> Result<()> {
+        let buf = match queue.tx.try_send(buf) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Closed(_)) => bail!("Connection closed"),
+            Err(TrySendError::Full(buf)) => buf,
+        };
+
+        match queue.config.overflow {
+            OverflowPolicy::Wait => {
+                queue
+                    .tx
+                    .send(buf)
+                    .await
+                    .map_err(|_| anyhow!("Connection closed"))?;
+            }
+            OverflowPolicy::Disconnect => {
+                queue.tx.close();
+                bail!(
+                    "Send queue is full ({} packets). Disconnecting",
+                    queue.config.capacity
+                );
+            }
+            OverflowPolicy::Drop => {
+                warn!(
+                    "{self_name}->{other_name}: Send queue is full ({} packets). Dropping a packet",
+                    queue.config.capacity,
+                    self_name = self.config.self_name,
+                    other_name = self.config.other_name
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<T: Unpin> Display for PacketStream<T> {
@@ -174,6 +509,52 @@ impl StreamConfig {
             deserialize_checksum: true,
             decode_rx: false,
             encode_tx: false,
+            client_side: false,
+            xor_table_seed: None,
+            xor_key_idx: None,
+            checksum_mode: ChecksumMode::Strict,
+            rx_context: PacketContext::default(),
+            max_pkt_len: u16::MAX,
+            recv_timeout: None,
+        }
+    }
+
+    /// Config for a game client connecting to the given `server`
+    pub fn client(self_name: String, server: ServiceID) -> Self {
+        Self {
+            self_name,
+            other_name: format!("{server:?}"),
+            serialize_checksum: true,
+            deserialize_checksum: false,
+            decode_rx: true,
+            encode_tx: true,
+            client_side: true,
+            xor_table_seed: None,
+            xor_key_idx: None,
+            checksum_mode: ChecksumMode::Strict,
+            rx_context: PacketContext::new(Some(server), None),
+            max_pkt_len: u16::MAX,
+            recv_timeout: None,
+        }
+    }
+}
+
+/// Application-level keepalives of an [`IPCPacketStream`], so that a dead
+/// peer is noticed even if the TCP connection doesn't report any error.
+/// Everything is disabled by default
+#[derive(Debug, Default, Clone, Copy)]
+pub struct KeepaliveConfig {
+    /// Send a Keepalive packet after this long without sending anything
+    pub interval: Option<Duration>,
+    /// Consider the peer dead after this long without receiving anything
+    pub timeout: Option<Duration>,
+}
+
+impl KeepaliveConfig {
+    fn send_queue(&self) -> SendQueueConfig {
+        SendQueueConfig {
+            keepalive: self.interval,
+            ..Default::default()
         }
     }
 }
@@ -197,9 +578,14 @@ pub enum Service {
     Party,
 }
 
-impl<T: Unpin + AsyncRead> IPCPacketStream<T> {
-    pub async fn from_host(self_id: Service, stream: T) -> Result<Self, anyhow::Error> {
-        let config = StreamConfig::ipc(self_id.to_string(), "New".to_string());
+impl<T: Unpin + AsyncRead + CloneWriter + 'static> IPCPacketStream<T> {
+    pub async fn from_host(
+        self_id: Service,
+        stream: T,
+        keepalive: KeepaliveConfig,
+    ) -> Result<Self, anyhow::Error> {
+        let mut config = StreamConfig::ipc(self_id.to_string(), "New".to_string());
+        config.recv_timeout = keepalive.timeout;
         let mut stream = PacketStream::new(stream, config);
         let p = stream
             .recv()
@@ -209,6 +595,9 @@ impl<T: Unpin + AsyncRead> IPCPacketStream<T> {
             bail!("Expected Connect packet, got {p:?}");
         };
         stream.config.other_name = other_id.to_string();
+        stream.config.rx_context =
+            PacketContext::new(Some(other_id.service), Some(self_id.service_id()));
+        stream.start_send_queue(keepalive.send_queue())?;
         Ok(Self {
             inner: stream,
             self_id,
@@ -217,14 +606,19 @@ impl<T: Unpin + AsyncRead> IPCPacketStream<T> {
     }
 }
 
-impl<T: Unpin + AsyncWrite> IPCPacketStream<T> {
+impl<T: Unpin + AsyncWrite + CloneWriter + 'static> IPCPacketStream<T> {
     pub async fn from_conn(
         self_id: Service,
         other_id: Service,
         stream: T,
+        keepalive: KeepaliveConfig,
     ) -> Result<Self, anyhow::Error> {
-        let config = StreamConfig::ipc(self_id.to_string(), other_id.to_string());
+        let mut config = StreamConfig::ipc(self_id.to_string(), other_id.to_string());
+        config.rx_context =
+            PacketContext::new(Some(other_id.service_id()), Some(self_id.service_id()));
+        config.recv_timeout = keepalive.timeout;
         let mut stream = PacketStream::new(stream, config);
+        stream.start_send_queue(keepalive.send_queue())?;
         stream.send(&Connect::from(self_id)).await?;
 
         Ok(Self {
@@ -236,8 +630,15 @@ impl<T: Unpin + AsyncWrite> IPCPacketStream<T> {
 }
 
 impl<T: Unpin + AsyncRead> IPCPacketStream<T> {
+    /// Receive the next packet other than a Keepalive.
+    /// This is cancellation-safe.
     pub async fn recv(&mut self) -> std::result::Result<Packet, RecvError> {
-        self.inner.recv().await
+        loop {
+            match self.inner.recv().await? {
+                Packet::Keepalive(_) => continue,
+                p => return Ok(p),
+            }
+        }
     }
 }
 
@@ -341,6 +742,9 @@ struct AsyncBufReader {
 }
 
 impl AsyncBufReader {
+    /// Size the buffer is shrunk to once it's empty
+    const SHRINK_LEN: usize = 0x1000;
+
     fn new() -> Self {
         Self {
             buf: Vec::new(),
@@ -382,6 +786,11 @@ impl AsyncBufReader {
             // faster equivalent of [`Self::make_contiguous()`]
             self.len = 0;
             self.offset = 0;
+            // don't hold onto the memory after an unusually large packet
+            if self.buf.len() > Self::SHRINK_LEN {
+                self.buf.truncate(Self::SHRINK_LEN);
+                self.buf.shrink_to_fit();
+            }
         } else {
             self.offset += len;
         }
@@ -394,9 +803,23 @@ impl AsyncBufReader {
     }
 }
 
+/// Decodes packets received from the client, and encodes the ones sent
+/// back. Each packet is XOR'd dword by dword, with the key of each dword
+/// derived from the previous one.
+///
+/// Packets received from the client carry a checksum in their header,
+/// which is the key that would follow the last dword, XOR'd with that
+/// (zero-padded) dword. Packets sent to the client usually come without
+/// a checksum field at all.
+///
+/// See [`PacketEncoder`] for the client side.
 #[derive(Debug)]
 pub struct PacketDecoder {
     pub xor_table_seed: u32,
+    /// Received headers contain a checksum field
+    pub rx_checksum: bool,
+    /// Sent headers contain a checksum field, which is then computed
+    pub tx_checksum: bool,
 
     first_packet_received: bool,
     pub xor_key_idx: u16,
@@ -407,125 +830,71 @@ pub struct PacketDecoder {
 impl PacketDecoder {
     const XOR_KEY_MASK: u32 = 0x3FFF;
     const XOR_ENCODE_KEY: u32 = 0x7ab38cf1;
+    /// XOR table seed used by the original LoginSvr in some captures.
+    /// Meant for debugging only, real connections should use random seeds
+    pub const FIXED_XOR_TABLE_SEED: u32 = 0x46631ab5;
+    /// Initial XOR key index matching [`Self::FIXED_XOR_TABLE_SEED`]
+    pub const FIXED_XOR_KEY_IDX: u16 = 0x1BB8;
 
-    fn new(xor_table_seed: Option<u32>, xor_key_idx: Option<u16>) -> Self {
+    pub fn new(xor_table_seed: Option<u32>, xor_key_idx: Option<u16>) -> Self {
         let xor_table_seed = xor_table_seed.unwrap_or_else(rand::random);
         let xor_key_idx: u16 =
             xor_key_idx.unwrap_or_else(rand::random) & (Self::XOR_KEY_MASK as u16);
 
         Self {
             xor_table_seed,
+            rx_checksum: true,
+            tx_checksum: false,
             first_packet_received: false,
             xor_key_idx,
-            xor_table: Self::gen_xor_table(xor_table_seed),
+            xor_table: gen_xor_table(xor_table_seed),
             xor_key: None,
         }
     }
 
-    fn decode(&mut self, data: &mut [u8]) -> Result<PacketDecodeResult, PacketDecodeError> {
-        let data_len = data.len();
-        let mut data_u32 = data
-            .chunks_exact_mut(4)
-            .map(|c| TryInto::<&mut [u8; 4]>::try_into(c).unwrap());
-        let Some(dword) = data_u32.next() else {
+    /// Decode the packet in-place. Only the first `pkt_len` bytes (as
+    /// specified in the header) are decoded, anything after is left as is.
+    ///
+    /// This can be called with just the first 4 bytes to get the packet
+    /// length first; nothing is mutated until the entire packet is given.
+    pub fn decode(&mut self, data: &mut [u8]) -> Result<PacketDecodeResult, PacketDecodeError> {
+        let Some(dword) = data.get(..4) else {
             return Ok(PacketDecodeResult::HeaderIncomplete);
         };
+        let org_dword = u32::from_le_bytes(dword.try_into().unwrap());
 
         let xor_key = *self.xor_key.get_or_insert_with(|| {
-            let expected_pkt_len = 14u32;
+            // the first packet is always C2SConnect with a 4-byte payload
+            let expected_pkt_len = Header::num_bytes(self.rx_checksum) as u32 + 4;
             let expected_dword: u32 = (Header::MAGIC as u32) | (expected_pkt_len << 16);
-            let dword_u32 = u32::from_le_bytes(*dword);
-            dword_u32 ^ expected_dword
+            org_dword ^ expected_dword
         });
 
-        let org_dword = u32::from_le_bytes(*dword);
-        let hdr_u32 = org_dword ^ xor_key;
-        let magic = (hdr_u32 & 0xFFFF) as u16;
-        let pkt_len = (hdr_u32 >> 16) as u16;
-        if magic != Header::MAGIC {
-            return Err(PacketDecodeError::InvalidMagic(magic));
-        }
-        if pkt_len as usize > data_len {
+        let pkt_len = parse_hdr_dword(org_dword ^ xor_key, self.rx_checksum)?;
+        if pkt_len as usize > data.len() {
             return Ok(PacketDecodeResult::PayloadIncomplete(pkt_len));
         }
 
-        // FIXME: for now this assumes the checksum is always present
-        let Some(recv_checksum) = data_u32.next() else {
-            // can happen if received pkt_len is malformed
-            return Ok(PacketDecodeResult::HeaderIncomplete);
-        };
-        let recv_checksum = u32::from_le_bytes(*recv_checksum);
-        *dword = hdr_u32.to_le_bytes();
-
-        let mut xor_key = self.get_dec_xor_key(org_dword);
-        for dword in data_u32 {
-            let org_dword = u32::from_le_bytes(*dword);
-            Self::store_u32(dword, org_dword ^ xor_key);
-            xor_key = self.get_dec_xor_key(org_dword);
-        }
-
-        // cant use [`ChunkExactMut::into_remainder()`] since we used .map()
-        let remainder = &mut data[data_len / 4 * 4..];
-        let mut dword = [0u8; 4];
-        dword[..remainder.len()].copy_from_slice(remainder);
-        let org_dword = u32::from_le_bytes(dword);
-        let dword_u32 = org_dword ^ xor_key;
-        remainder.copy_from_slice(&dword_u32.to_le_bytes()[..remainder.len()]);
-
-        let checksum = self.get_dec_xor_key(xor_key) ^ org_dword;
-        if checksum != recv_checksum {
-            return Err(PacketDecodeError::Checksum {
-                expected: recv_checksum,
-                calculated: checksum,
-                pkt_len,
-            });
-        }
+        let data = &mut data[..pkt_len as usize];
+        let (checksum, recv_checksum) = xor_decode(data, xor_key, self.rx_checksum, |idx| {
+            self.get_dec_xor_key(idx)
+        });
 
+        // the packet is decoded now, so move on to the next key even if
+        // the checksum doesn't match. It's up to the caller to drop it
         self.first_packet_received = true;
         self.xor_key = Some(self.get_dec_xor_key(self.xor_key_idx as u32));
         self.xor_key_idx = self.xor_key_idx.wrapping_add(1);
 
-        Ok(PacketDecodeResult::Done(pkt_len))
+        verify_checksum(checksum, recv_checksum, pkt_len)
     }
 
+    /// Encode the entire packet in-place. If [`Self::tx_checksum`] is set,
+    /// the checksum field is filled in as well.
     fn encode(&mut self, data: &mut [u8]) {
-        let data_len = data.len();
-        let mut data_u32 = data
-            .chunks_exact_mut(4)
-            .map(|c| TryInto::<&mut [u8; 4]>::try_into(c).unwrap());
-
-        let dword = data_u32.next().unwrap();
-        let xored_dword = u32::from_le_bytes(*dword) ^ Self::XOR_ENCODE_KEY;
-        Self::store_u32(dword, xored_dword);
-
-        let mut xor_key = self.get_enc_xor_key(xored_dword);
-        for dword in data_u32 {
-            let xored_dword = u32::from_le_bytes(*dword) ^ xor_key;
-            Self::store_u32(dword, xored_dword);
-            xor_key = self.get_enc_xor_key(xored_dword);
-        }
-
-        // cant use [`ChunkExactMut::into_remainder()`] since we used .map()
-        let remainder = &mut data[data_len / 4 * 4..];
-        let mut dword = [0u8; 4];
-        dword[..remainder.len()].copy_from_slice(remainder);
-        let org_dword = u32::from_le_bytes(dword);
-        let dword_u32 = org_dword ^ xor_key;
-        remainder.copy_from_slice(&dword_u32.to_le_bytes()[..remainder.len()]);
-    }
-
-    #[inline]
-    fn store_u32(dst: &mut [u8; 4], val: u32) {
-        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-        {
-            // SAFETY: x86 doesn't care about unaligned access
-            let dst_u32: &mut u32 = unsafe { &mut *(dst.as_mut_ptr() as *mut _) };
-            *dst_u32 = val
-        }
-        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
-        {
-            *dst = val.to_le_bytes();
-        }
+        xor_encode(data, Self::XOR_ENCODE_KEY, self.tx_checksum, |idx| {
+            self.get_enc_xor_key(idx)
+        });
     }
 
     #[inline]
@@ -534,43 +903,298 @@ impl PacketDecoder {
             true => 2,
             false => 1,
         };
-        unsafe {
-            *self
-                .xor_table
-                .get_unchecked(((idx & Self::XOR_KEY_MASK) * idx_mul) as usize)
-        }
+        get_xor_key(&self.xor_table, idx, idx_mul)
     }
 
     #[inline]
     fn get_enc_xor_key(&self, idx: u32) -> u32 {
-        unsafe {
-            *self
-                .xor_table
-                .get_unchecked((idx & Self::XOR_KEY_MASK) as usize)
+        get_xor_key(&self.xor_table, idx, 1)
+    }
+}
+
+/// Client-side counterpart of [`PacketDecoder`]. Encodes the packets sent
+/// to the server, and decodes the ones received from it.
+///
+/// The first packet (C2SConnect) can be encoded with any key, as the
+/// server figures it out by itself. It then replies with S2CConnect that
+/// contains the table seed and key index for all further packets, which
+/// have to be applied with [`Self::set_xor_seed`].
+#[derive(Debug)]
+pub struct PacketEncoder {
+    /// Sent headers contain a checksum field, which is then computed
+    pub tx_checksum: bool,
+    /// Received headers contain a checksum field
+    pub rx_checksum: bool,
+
+    first_packet_sent: bool,
+    /// Index of the key for the next packet header. [`None`] after the
+    /// first packet, until the seed from S2CConnect is set
+    xor_key_idx: Option<u16>,
+    xor_table: [u32; 0x8000],
+}
+
+impl PacketEncoder {
+    /// `xor_key_idx` picks the key for the first packet header. Random if
+    /// not specified
+    pub fn new(xor_key_idx: Option<u16>) -> Self {
+        let xor_key_idx: u16 =
+            xor_key_idx.unwrap_or_else(rand::random) & (PacketDecoder::XOR_KEY_MASK as u16);
+
+        Self {
+            tx_checksum: true,
+            rx_checksum: false,
+            first_packet_sent: false,
+            xor_key_idx: Some(xor_key_idx),
+            // the seed only affects the second half of the table, which
+            // isn't used until we get the real seed anyway
+            xor_table: gen_xor_table(0),
         }
     }
 
-    /// Hardcoded magic numbers
-    fn gen_xor_table(seed: u32) -> [u32; 0x8000] {
-        let mut tmp_seed: u32 = 0x8f54c37b;
+    /// Apply the table seed and key index received in S2CConnect
+    pub fn set_xor_seed(&mut self, xor_table_seed: u32, xor_key_idx: u16) {
+        self.xor_table = gen_xor_table(xor_table_seed);
+        // the server has already moved past the key it will use for
+        // our next packet
+        self.xor_key_idx = Some(xor_key_idx.wrapping_sub(1));
+    }
 
-        std::array::from_fn(|i| {
-            if i == 0x4000 {
-                tmp_seed = seed;
-            }
+    /// Encode the entire packet in-place. If [`Self::tx_checksum`] is set,
+    /// the checksum field is filled in as well.
+    pub fn encode(&mut self, data: &mut [u8]) -> Result<(), PacketEncodeError> {
+        let xor_key_idx = self.xor_key_idx.ok_or(PacketEncodeError::MissingXorSeed)?;
+        let idx_mul = match self.first_packet_sent {
+            true => 2,
+            false => 1,
+        };
 
-            let tmp_v = tmp_seed.wrapping_mul(0x2f6b6f5).wrapping_add(0x14698b7);
-            tmp_seed = tmp_v.wrapping_mul(0x2f6b6f5).wrapping_add(0x14698b7);
-            let p1 = (tmp_v >> 16).wrapping_mul(0x27f41c3).wrapping_add(0xb327bd) >> 16;
-            let p2 = (tmp_seed >> 16)
-                .wrapping_mul(0x27f41c3)
-                .wrapping_add(0xb327bd)
-                & 0xffff0000;
-            p1 | p2
-        })
+        let hdr_key = get_xor_key(&self.xor_table, xor_key_idx as u32, idx_mul);
+        xor_encode(data, hdr_key, self.tx_checksum, |idx| {
+            get_xor_key(&self.xor_table, idx, idx_mul)
+        });
+
+        self.xor_key_idx = match self.first_packet_sent {
+            true => Some(xor_key_idx.wrapping_add(1)),
+            false => None,
+        };
+        self.first_packet_sent = true;
+        Ok(())
+    }
+
+    /// Decode the packet in-place, same as [`PacketDecoder::decode`]
+    pub fn decode(&mut self, data: &mut [u8]) -> Result<PacketDecodeResult, PacketDecodeError> {
+        let Some(dword) = data.get(..4) else {
+            return Ok(PacketDecodeResult::HeaderIncomplete);
+        };
+        let org_dword = u32::from_le_bytes(dword.try_into().unwrap());
+        let xor_key = PacketDecoder::XOR_ENCODE_KEY;
+
+        let pkt_len = parse_hdr_dword(org_dword ^ xor_key, self.rx_checksum)?;
+        if pkt_len as usize > data.len() {
+            return Ok(PacketDecodeResult::PayloadIncomplete(pkt_len));
+        }
+
+        let data = &mut data[..pkt_len as usize];
+        let (checksum, recv_checksum) = xor_decode(data, xor_key, self.rx_checksum, |idx| {
+            get_xor_key(&self.xor_table, idx, 1)
+        });
+        verify_checksum(checksum, recv_checksum, pkt_len)
     }
 }
 
+/// Either side of the XOR encoding, so [`PacketStream`] doesn't need to
+/// care which one it uses
+trait XorCodec {
+    fn decode(&mut self, data: &mut [u8]) -> Result<PacketDecodeResult, PacketDecodeError>;
+    fn encode(&mut self, data: &mut [u8]) -> Result<(), PacketEncodeError>;
+}
+
+impl XorCodec for PacketDecoder {
+    fn decode(&mut self, data: &mut [u8]) -> Result<PacketDecodeResult, PacketDecodeError> {
+        PacketDecoder::decode(self, data)
+    }
+
+    fn encode(&mut self, data: &mut [u8]) -> Result<(), PacketEncodeError> {
+        PacketDecoder::encode(self, data);
+        Ok(())
+    }
+}
+
+impl XorCodec for PacketEncoder {
+    fn decode(&mut self, data: &mut [u8]) -> Result<PacketDecodeResult, PacketDecodeError> {
+        PacketEncoder::decode(self, data)
+    }
+
+    fn encode(&mut self, data: &mut [u8]) -> Result<(), PacketEncodeError> {
+        PacketEncoder::encode(self, data)
+    }
+}
+
+/// Get whichever codec the stream uses, if any
+fn xor_codec<'a>(
+    decoder: &'a mut Option<Box<PacketDecoder>>,
+    encoder: &'a mut Option<Box<PacketEncoder>>,
+) -> Option<&'a mut dyn XorCodec> {
+    match (decoder, encoder) {
+        (Some(decoder), _) => Some(decoder.as_mut()),
+        (_, Some(encoder)) => Some(encoder.as_mut()),
+        _ => None,
+    }
+}
+
+/// Check the magic of a decoded header dword and return the packet length
+fn parse_hdr_dword(hdr_u32: u32, checksum: bool) -> Result<u16, PacketDecodeError> {
+    let magic = (hdr_u32 & 0xFFFF) as u16;
+    let pkt_len = (hdr_u32 >> 16) as u16;
+    if magic != Header::MAGIC {
+        return Err(PacketDecodeError::InvalidMagic(magic));
+    }
+    if (pkt_len as usize) < Header::num_bytes(checksum) {
+        return Err(PacketDecodeError::TooSmall(pkt_len));
+    }
+    Ok(pkt_len)
+}
+
+fn verify_checksum(
+    checksum: u32,
+    recv_checksum: Option<u32>,
+    pkt_len: u16,
+) -> Result<PacketDecodeResult, PacketDecodeError> {
+    match recv_checksum {
+        Some(recv_checksum) if recv_checksum != checksum => Err(PacketDecodeError::Checksum {
+            expected: recv_checksum,
+            calculated: checksum,
+            pkt_len,
+        }),
+        _ => Ok(PacketDecodeResult::Done(pkt_len)),
+    }
+}
+
+/// XOR the entire packet in-place, starting with `hdr_key`. The key of
+/// each following dword is looked up with the previous encoded dword.
+/// With `checksum`, the second dword is left out and then filled with
+/// the checksum.
+fn xor_encode(data: &mut [u8], hdr_key: u32, checksum: bool, get_key: impl Fn(u32) -> u32) {
+    let data_len = data.len();
+    let mut data_u32 = data
+        .chunks_exact_mut(4)
+        .map(|c| TryInto::<&mut [u8; 4]>::try_into(c).unwrap());
+
+    let dword = data_u32.next().unwrap();
+    let xored_dword = u32::from_le_bytes(*dword) ^ hdr_key;
+    store_u32(dword, xored_dword);
+    if checksum {
+        // filled in at the end
+        data_u32.next().unwrap();
+    }
+
+    let mut xor_key = get_key(xored_dword);
+    for dword in data_u32 {
+        let xored_dword = u32::from_le_bytes(*dword) ^ xor_key;
+        store_u32(dword, xored_dword);
+        xor_key = get_key(xored_dword);
+    }
+
+    // cant use [`ChunkExactMut::into_remainder()`] since we used .map()
+    let remainder = &mut data[data_len / 4 * 4..];
+    let mut dword = [0u8; 4];
+    dword[..remainder.len()].copy_from_slice(remainder);
+    let org_dword = u32::from_le_bytes(dword);
+    let dword_u32 = org_dword ^ xor_key;
+    remainder.copy_from_slice(&dword_u32.to_le_bytes()[..remainder.len()]);
+
+    if checksum {
+        // same as on decode, the padding bytes don't count
+        let mut dword = [0u8; 4];
+        dword[..remainder.len()].copy_from_slice(remainder);
+        let checksum = get_key(xor_key) ^ u32::from_le_bytes(dword);
+        data[4..8].copy_from_slice(&checksum.to_le_bytes());
+    }
+}
+
+/// Counterpart of [`xor_encode`]. Returns the checksum calculated from
+/// the encoded data, and the one found in the header (if any).
+fn xor_decode(
+    data: &mut [u8],
+    hdr_key: u32,
+    checksum: bool,
+    get_key: impl Fn(u32) -> u32,
+) -> (u32, Option<u32>) {
+    let data_len = data.len();
+    let mut data_u32 = data
+        .chunks_exact_mut(4)
+        .map(|c| TryInto::<&mut [u8; 4]>::try_into(c).unwrap());
+
+    let dword = data_u32.next().unwrap();
+    let org_dword = u32::from_le_bytes(*dword);
+    store_u32(dword, org_dword ^ hdr_key);
+    // the checksum isn't encoded, and doesn't affect the following keys
+    let recv_checksum = match checksum {
+        true => Some(u32::from_le_bytes(*data_u32.next().unwrap())),
+        false => None,
+    };
+
+    let mut xor_key = get_key(org_dword);
+    for dword in data_u32 {
+        let org_dword = u32::from_le_bytes(*dword);
+        store_u32(dword, org_dword ^ xor_key);
+        xor_key = get_key(org_dword);
+    }
+
+    // cant use [`ChunkExactMut::into_remainder()`] since we used .map()
+    let remainder = &mut data[data_len / 4 * 4..];
+    let mut dword = [0u8; 4];
+    dword[..remainder.len()].copy_from_slice(remainder);
+    let org_dword = u32::from_le_bytes(dword);
+    let dword_u32 = org_dword ^ xor_key;
+    remainder.copy_from_slice(&dword_u32.to_le_bytes()[..remainder.len()]);
+
+    (get_key(xor_key) ^ org_dword, recv_checksum)
+}
+
+#[inline]
+fn store_u32(dst: &mut [u8; 4], val: u32) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        // SAFETY: x86 doesn't care about unaligned access
+        let dst_u32: &mut u32 = unsafe { &mut *(dst.as_mut_ptr() as *mut _) };
+        *dst_u32 = val
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        *dst = val.to_le_bytes();
+    }
+}
+
+/// `idx_mul` is 2 for all packets after the first C2SConnect, and only
+/// then the seeded half of the table is used
+#[inline]
+fn get_xor_key(xor_table: &[u32; 0x8000], idx: u32, idx_mul: u32) -> u32 {
+    debug_assert!(idx_mul <= 2);
+    // SAFETY: the index is at most 0x3FFF * 2
+    unsafe { *xor_table.get_unchecked(((idx & PacketDecoder::XOR_KEY_MASK) * idx_mul) as usize) }
+}
+
+/// Hardcoded magic numbers
+fn gen_xor_table(seed: u32) -> [u32; 0x8000] {
+    let mut tmp_seed: u32 = 0x8f54c37b;
+
+    std::array::from_fn(|i| {
+        if i == 0x4000 {
+            tmp_seed = seed;
+        }
+
+        let tmp_v = tmp_seed.wrapping_mul(0x2f6b6f5).wrapping_add(0x14698b7);
+        tmp_seed = tmp_v.wrapping_mul(0x2f6b6f5).wrapping_add(0x14698b7);
+        let p1 = (tmp_v >> 16).wrapping_mul(0x27f41c3).wrapping_add(0xb327bd) >> 16;
+        let p2 = (tmp_seed >> 16)
+            .wrapping_mul(0x27f41c3)
+            .wrapping_add(0xb327bd)
+            & 0xffff0000;
+        p1 | p2
+    })
+}
+
 #[derive(Debug)]
 pub enum PacketDecodeResult {
     HeaderIncomplete,
@@ -582,6 +1206,8 @@ pub enum PacketDecodeResult {
 pub enum PacketDecodeError {
     #[error("Invalid header magic (expected {:#04x}, got {0:#04x})", Header::MAGIC)]
     InvalidMagic(u16),
+    #[error("Packet size smaller than header size ({0:#04x})")]
+    TooSmall(u16),
     #[error("Checksum mismatch (expected {expected:#08x}, got {calculated:#08x})")]
     Checksum {
         expected: u32,
@@ -594,18 +1220,27 @@ impl PacketDecodeError {
     pub fn decoded_len(&self) -> usize {
         match self {
             Self::Checksum { pkt_len, .. } => *pkt_len as _,
-            Self::InvalidMagic { .. } => 0,
+            Self::InvalidMagic { .. } | Self::TooSmall(_) => 0,
         }
     }
 }
 
+#[derive(Debug, Error)]
+pub enum PacketEncodeError {
+    #[error("Missing the XOR seed from S2CConnect, can't encode any more packets")]
+    MissingXorSeed,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_decode_basic() {
-        let mut decoder = PacketDecoder::new(Some(0x46631ab5), Some(0x1BB8));
+        let mut decoder = PacketDecoder::new(
+            Some(PacketDecoder::FIXED_XOR_TABLE_SEED),
+            Some(PacketDecoder::FIXED_XOR_KEY_IDX),
+        );
 
         let mut enc: Vec<u8> = b"\x68\xff\x4c\x25\x5c\xee\xd5\x08\x22\xe3\xcc\x11\x5f\x6f".into();
         let len = match decoder.decode(&mut enc).unwrap() {
@@ -630,4 +1265,299 @@ mod tests {
         assert_eq!(len, enc.len());
         println!("{:x?}", enc);
     }
+
+    #[test]
+    fn test_client_codec() {
+        // same packets as in test_decode_basic, but from the client side
+        let enc1: Vec<u8> = b"\x68\xff\x4c\x25\x5c\xee\xd5\x08\x22\xe3\xcc\x11\x5f\x6f".into();
+        let enc2: Vec<u8> = b"\x5f\x57\xd4\xee\xba\x58\xf5\x18\x32\x97\xa5\x47\x6e\x12\xf8\x25\
+			\x62\x0d\x31\xee\x61\x0d\xe5\x84\x91\xec"
+            .into();
+        let s2c_connect: Vec<u8> =
+            b"\xe2\xb7\x12\x00\x65\x00\xb5\x1a\x63\x46\x9e\x4c\x00\x00\x00\x00\xb9\x1b".into();
+
+        let mut decoder = PacketDecoder::new(
+            Some(PacketDecoder::FIXED_XOR_TABLE_SEED),
+            Some(PacketDecoder::FIXED_XOR_KEY_IDX),
+        );
+        let mut dec1 = enc1.clone();
+        decoder.decode(&mut dec1).unwrap();
+        let mut enc_s2c_connect = s2c_connect.clone();
+        decoder.encode(&mut enc_s2c_connect);
+        let mut dec2 = enc2.clone();
+        decoder.decode(&mut dec2).unwrap();
+
+        // the original client used this key for the first packet
+        let mut encoder = PacketEncoder::new(Some(0x29));
+        encoder.encode(&mut dec1).unwrap();
+        assert_eq!(dec1, enc1);
+
+        let res = encoder.decode(&mut enc_s2c_connect).unwrap();
+        assert!(matches!(res, PacketDecodeResult::Done(0x12)));
+        assert_eq!(enc_s2c_connect, s2c_connect);
+
+        let res = encoder.encode(&mut dec2.clone());
+        assert!(matches!(res, Err(PacketEncodeError::MissingXorSeed)));
+        encoder.set_xor_seed(
+            PacketDecoder::FIXED_XOR_TABLE_SEED,
+            PacketDecoder::FIXED_XOR_KEY_IDX + 1,
+        );
+        encoder.encode(&mut dec2).unwrap();
+        assert_eq!(dec2, enc2);
+    }
+
+    #[test]
+    fn test_client_stream() {
+        use packet::pkt_login::*;
+        use smol::Async;
+        use std::os::unix::net::UnixStream;
+
+        let (client, server) = UnixStream::pair().unwrap();
+        let config = StreamConfig::client("Client".into(), ServiceID::LoginSvr);
+        let mut client = PacketStream::new(Async::new(client).unwrap(), config);
+        let mut server = PacketStream::new(Async::new(server).unwrap(), login_config());
+
+        smol::block_on(async {
+            let p = C2SConnect { auth_key: 0x1234 };
+            client.send(&p).await.unwrap();
+            assert_eq!(server.recv().await.unwrap(), Packet::C2SConnect(p));
+
+            let decoder = server.decoder.as_ref().unwrap();
+            let p = S2CConnect {
+                xor_seed_2: decoder.xor_table_seed,
+                auth_key: 0x1234,
+                user_idx: 1,
+                xor_key_idx: decoder.xor_key_idx,
+            };
+            server.send(&p).await.unwrap();
+            assert_eq!(client.recv().await.unwrap(), Packet::S2CConnect(p));
+
+            // the following packets use the seed from S2CConnect
+            for i in 0..8 {
+                let p = C2SCheckVersion {
+                    client_version: i,
+                    ..Default::default()
+                };
+                client.send(&p).await.unwrap();
+                assert_eq!(server.recv().await.unwrap(), Packet::C2SCheckVersion(p));
+
+                let p = S2CCheckVersion {
+                    server_version: i,
+                    ..Default::default()
+                };
+                server.send(&p).await.unwrap();
+                assert_eq!(client.recv().await.unwrap(), Packet::S2CCheckVersion(p));
+            }
+        });
+    }
+
+    #[test]
+    fn test_send_queue() {
+        use packet::pkt_event::Keepalive;
+        use smol::Async;
+        use std::os::unix::net::UnixStream;
+
+        let stream_pair = |overflow| {
+            let (a, b) = UnixStream::pair().unwrap();
+            let config = || StreamConfig::ipc("a".into(), "b".into());
+            let mut a = PacketStream::new(Async::new(a).unwrap(), config());
+            let b = PacketStream::new(Async::new(b).unwrap(), config());
+            a.start_send_queue(SendQueueConfig {
+                capacity: 2,
+                overflow,
+                ..Default::default()
+            })
+            .unwrap();
+            (a, b)
+        };
+
+        executor::run_until(async move {
+            // nothing yields between the sends, so the queue can't be
+            // emptied in the meantime
+            let (mut a, mut b) = stream_pair(OverflowPolicy::Disconnect);
+            a.send(&Keepalive {}).await.unwrap();
+            a.send(&Keepalive {}).await.unwrap();
+            a.send(&Keepalive {}).await.unwrap_err();
+            a.send(&Keepalive {}).await.unwrap_err();
+            // the queued packets are still sent
+            b.recv().await.unwrap();
+            b.recv().await.unwrap();
+            assert!(matches!(b.recv().await, Err(RecvError::Terminated)));
+
+            let (mut a, mut b) = stream_pair(OverflowPolicy::Wait);
+            for _ in 0..8 {
+                a.send(&Keepalive {}).await.unwrap();
+            }
+            drop(a);
+            for _ in 0..8 {
+                b.recv().await.unwrap();
+            }
+            assert!(matches!(b.recv().await, Err(RecvError::Terminated)));
+
+            let (mut a, mut b) = stream_pair(OverflowPolicy::Drop);
+            for _ in 0..8 {
+                a.send(&Keepalive {}).await.unwrap();
+            }
+            drop(a);
+            b.recv().await.unwrap();
+            b.recv().await.unwrap();
+            assert!(matches!(b.recv().await, Err(RecvError::Terminated)));
+        });
+
+        // encoded packets can't be skipped
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut a = PacketStream::new(Async::new(a).unwrap(), login_config());
+        let err = a
+            .start_send_queue(SendQueueConfig {
+                capacity: 2,
+                overflow: OverflowPolicy::Drop,
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_keepalive() {
+        use smol::Async;
+        use std::os::unix::net::UnixStream;
+
+        let world = Service::WorldSvr {
+            server: 1,
+            channel: 1,
+        };
+        let ipc_pair = move |host_keepalive, conn_keepalive| async move {
+            let (a, b) = UnixStream::pair().unwrap();
+            let host =
+                IPCPacketStream::from_host(Service::Party, Async::new(a).unwrap(), host_keepalive);
+            let conn = IPCPacketStream::from_conn(
+                world,
+                Service::Party,
+                Async::new(b).unwrap(),
+                conn_keepalive,
+            );
+            let (host, conn) = futures::join!(host, conn);
+            (host.unwrap(), conn.unwrap())
+        };
+        let keepalive = KeepaliveConfig {
+            interval: Some(Duration::from_millis(20)),
+            timeout: Some(Duration::from_millis(100)),
+        };
+
+        executor::run_until(async move {
+            // keepalives are never returned from recv(), but they keep
+            // the connection alive
+            let (mut host, _conn) = ipc_pair(keepalive, keepalive).await;
+            let recv = async { Some(host.recv().await) };
+            let timer = async {
+                Timer::after(Duration::from_millis(300)).await;
+                None
+            };
+            assert!(smol::future::or(recv, timer).await.is_none());
+
+            // the peer is alive, but doesn't send anything
+            let (mut host, _conn) = ipc_pair(keepalive, KeepaliveConfig::default()).await;
+            assert!(matches!(host.recv().await, Err(RecvError::Timeout(_))));
+        });
+    }
+
+    fn login_config() -> StreamConfig {
+        StreamConfig {
+            self_name: "LoginSvr".into(),
+            other_name: "User".into(),
+            serialize_checksum: false,
+            deserialize_checksum: true,
+            encode_tx: true,
+            decode_rx: true,
+            client_side: false,
+            xor_table_seed: None,
+            xor_key_idx: None,
+            checksum_mode: ChecksumMode::Strict,
+            rx_context: PacketContext::new(None, Some(ServiceID::LoginSvr)),
+            max_pkt_len: 0x1000,
+            recv_timeout: None,
+        }
+    }
+
+    #[test]
+    fn test_random_xor_seed() {
+        let xor_seed = || {
+            let stream = PacketStream::new(futures::io::empty(), login_config());
+            let decoder = stream.decoder.unwrap();
+            (decoder.xor_table_seed, decoder.xor_key_idx)
+        };
+        assert_ne!(xor_seed(), xor_seed());
+    }
+
+    #[test]
+    fn test_recv_too_small() {
+        // pkt_len (4) is shorter than the header itself
+        let data = futures::io::Cursor::new(b"\xe2\xb7\x04\x00\x00\x00\x00\x00\x00\x00".to_vec());
+        let mut stream = PacketStream::new(data, StreamConfig::ipc("a".into(), "b".into()));
+        let err = futures::executor::block_on(stream.recv()).unwrap_err();
+        assert!(matches!(
+            err,
+            RecvError::Malformed(HeaderDeserializeError::TooSmall { found: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn test_recv_too_long() {
+        let data = futures::io::Cursor::new(b"\xe2\xb7\x01\x10\x00\x00\x00\x00\x00\x00".to_vec());
+        let mut config = StreamConfig::ipc("a".into(), "b".into());
+        config.max_pkt_len = 0x1000;
+        let mut stream = PacketStream::new(data, config);
+        let err = futures::executor::block_on(stream.recv()).unwrap_err();
+        assert!(matches!(err, RecvError::TooLong { len: 0x1001, .. }));
+    }
+
+    #[test]
+    fn test_recv_invalid_length() {
+        // Keepalive with a 1-byte payload, then a correct one
+        let data = futures::io::Cursor::new(
+            b"\xe2\xb7\x0b\x00\x00\x00\x00\x00\xb3\x02\xff\
+            \xe2\xb7\x0a\x00\x00\x00\x00\x00\xb3\x02"
+                .to_vec(),
+        );
+        let mut stream = PacketStream::new(data, StreamConfig::ipc("a".into(), "b".into()));
+        let err = futures::executor::block_on(stream.recv()).unwrap_err();
+        assert!(matches!(
+            err,
+            RecvError::InvalidLength {
+                id: 0x2b3,
+                len: 1,
+                expected: 0
+            }
+        ));
+        // the stream can be still used
+        let p = futures::executor::block_on(stream.recv()).unwrap();
+        assert!(matches!(p, Packet::Keepalive(_)));
+    }
+
+    #[test]
+    fn test_recv_checksum_mode() {
+        // C2SConnect from test_decode_basic with a corrupted checksum
+        let mut data = b"\x68\xff\x4c\x25\x5c\xee\xd5\x08\x22\xe3\xcc\x11\x5f\x6f".to_vec();
+        data[4] ^= 0x1;
+
+        let recv = |checksum_mode| {
+            let config = StreamConfig {
+                xor_table_seed: Some(PacketDecoder::FIXED_XOR_TABLE_SEED),
+                xor_key_idx: Some(PacketDecoder::FIXED_XOR_KEY_IDX),
+                checksum_mode,
+                ..login_config()
+            };
+            let data = futures::io::Cursor::new(data.clone());
+            let mut stream = PacketStream::new(data, config);
+            futures::executor::block_on(stream.recv())
+        };
+
+        let err = recv(ChecksumMode::Strict).unwrap_err();
+        assert!(matches!(
+            err,
+            RecvError::Decode(PacketDecodeError::Checksum { pkt_len: 14, .. })
+        ));
+        let p = recv(ChecksumMode::Lenient).unwrap();
+        assert!(matches!(p, Packet::C2SConnect(_)));
+    }
 }
@@ -40,7 +40,7 @@ pub struct Listener {
     tcp_listener: Async<TcpListener>,
     worlds: BorrowRegistry<WorldConnection, ()>,
     servers: LockedVec<Server>,
-    _args: Arc<crate::args::Config>,
+    args: Arc<crate::args::Config>,
 }
 
 struct Server {
@@ -59,7 +59,7 @@ impl Listener {
             tcp_listener,
             worlds: BorrowRegistry::new(128),
             servers: LockedVec::with_capacity(1),
-            _args: args.clone(),
+            args: args.clone(),
         })
     }
 
@@ -70,6 +70,7 @@ impl Listener {
         );
 
         self.start_offline_grooming();
+        let keepalive = self.args.common.ipc_keepalive();
 
         loop {
             let (stream, _) = self.tcp_listener.accept().await?;
@@ -80,9 +81,15 @@ impl Listener {
             executor::spawn_local(async move {
                 info!("Listener: new connection ...");
 
-                let stream = IPCPacketStream::from_host(Service::Party, stream)
-                    .await
-                    .unwrap();
+                let stream =
+                    match IPCPacketStream::from_host(Service::Party, stream, keepalive).await {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            error!("Listener: {e}. Closing");
+                            listener.worlds.unregister(&conn_ref);
+                            return;
+                        }
+                    };
 
                 let id = stream.other_id;
                 let Service::WorldSvr { server, channel } = id else {
@@ -320,8 +327,7 @@ impl WorldConnection {
             };
         }
         match p {
-            Packet::ClientConnect(p) => {
-                let p = ClientConnectReq::deserialize_no_hdr(&p.bytes)?;
+            Packet::ClientConnectReq(p) => {
                 let char_id = p.char_id;
 
                 let (mut party_stats, world_ids) = {
@@ -422,7 +428,6 @@ impl WorldConnection {
                             s
                         })
                         .unwrap_or_default(),
-                    ..Default::default()
                 };
                 self.stream.send(&party_stats_resp).await?;
             }
@@ -2,14 +2,18 @@
 // Copyright(c) 2023 Darek Stojaczyk
 
 use crate::executor;
+use crate::packet_log::{Direction, PacketLogWriter};
 use crate::packet_stream::{PacketStream, StreamConfig};
 use clap::Args;
 use futures::{AsyncRead, AsyncReadExt, AsyncWrite};
 use log::{error, info};
+use packet::Packet;
 
 use std::fmt::Display;
 use std::net::TcpStream;
 use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use std::{net::TcpListener, sync::Arc};
 
 use anyhow::Result;
@@ -18,7 +22,9 @@ use smol::Async;
 /// Man in the middle for any cabal service serving cabal packets.
 ///
 /// All packets are dumped to stdout. The ones that are known are pretty
-/// printed.
+/// printed, either as Rust structs or as JSON (--json). With --record-dir,
+/// each connection is additionally saved to a packet log that can be
+/// replayed against our own services.
 #[derive(Args, Debug, Default)]
 #[command(about, long_about, verbatim_doc_comment, disable_help_flag = true)]
 pub struct ProxyArgs {
@@ -26,6 +32,12 @@ pub struct ProxyArgs {
     pub upstream_port: u16,
     #[clap(long = "downstream-port", visible_alias = "dp")]
     pub downstream_port: u16,
+    /// Directory to save packet logs into, one file per connection.
+    #[clap(long = "record-dir")]
+    pub record_dir: Option<PathBuf>,
+    /// Print the packets as JSON, one per line.
+    #[clap(long)]
+    pub json: bool,
     #[clap(hide = true, long, short, action = clap::ArgAction::Help)]
     help: Option<bool>,
 }
@@ -61,6 +73,7 @@ impl Listener {
             })
             .unwrap();
 
+        let mut log_idx = 0;
         loop {
             let (upstream, _) = self.tcp_listener.accept().await?;
             info!("Connecting to downstream: {}", proxyargs.downstream_port);
@@ -71,6 +84,11 @@ impl Listener {
             let upstream_id = upstream.as_raw_fd();
             let downstream_id = downstream.as_raw_fd();
 
+            let log = match &proxyargs.record_dir {
+                Some(dir) => Some(create_log(dir, &mut log_idx)?),
+                None => None,
+            };
+
             let upstream = upstream.split();
             let downstream = downstream.split();
 
@@ -81,6 +99,8 @@ impl Listener {
                     downstream.1,
                     StreamConfig::ipc("?".into(), "?".into()),
                 ),
+                log: log.clone(),
+                json: proxyargs.json,
                 args: self.args.clone(),
             };
 
@@ -91,6 +111,8 @@ impl Listener {
                     downstream.0,
                     StreamConfig::ipc("?".into(), "?".into()),
                 ),
+                log,
+                json: proxyargs.json,
                 args: self.args.clone(),
             };
 
@@ -121,11 +143,37 @@ impl Listener {
     }
 }
 
+/// Create a new packet log in `dir`, named after the current time and
+/// `log_idx`. Existing files are never overwritten, even if the proxy was
+/// restarted within the same second
+fn create_log(dir: &Path, log_idx: &mut u32) -> Result<PacketLogWriter> {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    loop {
+        let path = dir.join(format!("{timestamp}_{log_idx}.log"));
+        *log_idx += 1;
+        match PacketLogWriter::create(&path) {
+            Ok(log) => {
+                info!("Recording connection to {}", path.display());
+                return Ok(log);
+            }
+            Err(e)
+                if e.downcast_ref::<std::io::Error>()
+                    .is_some_and(|e| e.kind() == std::io::ErrorKind::AlreadyExists) => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct UpConnection<U: Unpin + AsyncRead, D: Unpin + AsyncWrite> {
     pub id: i32,
     pub stream: PacketStream<U>,
     pub downstream: PacketStream<D>,
+    pub log: Option<PacketLogWriter>,
+    pub json: bool,
     pub args: Arc<crate::args::Config>,
 }
 
@@ -134,6 +182,8 @@ pub struct DwConnection<U: Unpin + AsyncWrite, D: Unpin + AsyncRead> {
     pub id: i32,
     pub stream: PacketStream<U>,
     pub downstream: PacketStream<D>,
+    pub log: Option<PacketLogWriter>,
+    pub json: bool,
     pub args: Arc<crate::args::Config>,
 }
 
@@ -149,11 +199,27 @@ impl<U: Unpin + AsyncWrite, D: Unpin + AsyncRead> Display for DwConnection<U, D>
     }
 }
 
+fn fmt_packet(p: &Packet, json: bool) -> String {
+    if json {
+        if let Ok(str) = serde_json::to_string(p) {
+            return str;
+        }
+    }
+    format!("{p:?}")
+}
+
 impl<U: Unpin + AsyncRead, D: Unpin + AsyncWrite> UpConnection<U, D> {
     pub async fn recv_upstream(mut self) -> Result<()> {
         loop {
             let p = self.stream.recv().await?;
-            info!("{self}: Got up packet({:#x}): {p:?}", p.id());
+            info!(
+                "{self}: Got up packet({:#x}): {}",
+                p.id(),
+                fmt_packet(&p, self.json)
+            );
+            if let Some(log) = &mut self.log {
+                log.record(Direction::Rx, &p)?;
+            }
             self.downstream.send(&p).await?;
         }
     }
@@ -163,7 +229,14 @@ impl<U: Unpin + AsyncWrite, D: Unpin + AsyncRead> DwConnection<U, D> {
     pub async fn recv_downstream(mut self) -> Result<()> {
         loop {
             let p = self.downstream.recv().await?;
-            info!("{self}: Got dw packet({:#x}): {p:?}", p.id());
+            info!(
+                "{self}: Got dw packet({:#x}): {}",
+                p.id(),
+                fmt_packet(&p, self.json)
+            );
+            if let Some(log) = &mut self.log {
+                log.record(Direction::Tx, &p)?;
+            }
             self.stream.send(&p).await?;
         }
     }
@@ -0,0 +1,124 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+//! Replay a recorded [`packet_log`](crate::packet_log) against a service.
+//!
+//! The replay driver impersonates the peer of the recorded service:
+//! every `rx` entry is sent to the service, and every `tx` entry is
+//! expected to be received back. Any difference is reported as a
+//! [`ReplayMismatch`].
+
+use crate::packet_log::{Direction, PacketLogEntry};
+use crate::packet_stream::PacketStream;
+
+use anyhow::Result;
+use async_proc::select;
+use futures::{AsyncRead, AsyncWrite, FutureExt};
+use log::trace;
+use packet::pkt_common::Unknown;
+use packet::Payload;
+use smol::Timer;
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::time::Duration;
+
+pub struct Replay {
+    entries: Vec<PacketLogEntry>,
+    /// Packet IDs whose payload is not compared, only the ID itself.
+    /// Useful for packets with timestamps, random keys, etc.
+    pub ignore_payload: HashSet<u16>,
+    /// How long to wait for each expected packet
+    pub recv_timeout: Duration,
+}
+
+#[derive(Debug)]
+pub struct ReplayMismatch {
+    /// Index of the expected entry in the log
+    pub index: usize,
+    pub expected: PacketLogEntry,
+    /// (id, payload) of the packet received instead, or None if nothing
+    /// was received before the timeout
+    pub got: Option<(u16, Vec<u8>)>,
+}
+
+impl Replay {
+    pub fn new(entries: Vec<PacketLogEntry>) -> Self {
+        Self {
+            entries,
+            ignore_payload: HashSet::new(),
+            recv_timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Play all `rx` entries into the stream and compare the responses
+    /// with `tx` entries. Stops at the first missing response.
+    pub async fn run<T: Unpin + AsyncRead + AsyncWrite>(
+        &self,
+        stream: &mut PacketStream<T>,
+    ) -> Result<Vec<ReplayMismatch>> {
+        let mut mismatches = Vec::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            match entry.direction {
+                Direction::Rx => {
+                    trace!("Replay: #{index} sending {:#x}", entry.id);
+                    stream
+                        .send(&Unknown {
+                            id: entry.id,
+                            data: entry.payload.clone().into(),
+                        })
+                        .await?;
+                }
+                Direction::Tx => {
+                    let p = select! {
+                        p = stream.recv().fuse() => Some(p?),
+                        _ = Timer::after(self.recv_timeout).fuse() => None,
+                    };
+                    let got = match p {
+                        Some(p) => {
+                            let mut payload = Vec::new();
+                            p.serialize_no_hdr(&mut payload)?;
+                            (p.id(), payload)
+                        }
+                        None => {
+                            mismatches.push(ReplayMismatch {
+                                index,
+                                expected: entry.clone(),
+                                got: None,
+                            });
+                            break;
+                        }
+                    };
+
+                    trace!("Replay: #{index} received {:#x}", got.0);
+                    let payload_matches =
+                        self.ignore_payload.contains(&got.0) || got.1 == entry.payload;
+                    if got.0 != entry.id || !payload_matches {
+                        mismatches.push(ReplayMismatch {
+                            index,
+                            expected: entry.clone(),
+                            got: Some(got),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(mismatches)
+    }
+}
+
+impl Display for ReplayMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "#{}: expected: {}", self.index, self.expected)?;
+        match &self.got {
+            Some((id, payload)) => write!(
+                f,
+                "#{}: got: {id:#06x} {}",
+                self.index,
+                hex::encode(payload)
+            ),
+            None => write!(f, "#{}: got nothing", self.index),
+        }
+    }
+}
@@ -0,0 +1,491 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+//! Hex dumps with `@annotate` lines, as found in packet definition comments:
+//! ```text
+//! > @annotate-cfg [clamp = [7, 54]]
+//! 0050   00 00 00 00 00 00 00 00 00 00 01 00 00 00 01 00   ................
+//! > @annotate [0-4] unk0
+//! > @annotate [4-16] unk1
+//! ```
+//! Each `[start-end]` range (end exclusive) refers to the columns of the
+//! preceding dump line. `@annotate-cfg` lines configure the editor
+//! highlighting and are preserved as-is.
+
+use super::hexdump;
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use packet::{FieldLayout, Header, Packet};
+
+use std::fmt::Write;
+use std::io::Read;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Configuration that makes the annotations render correctly with
+/// the editor extension we use.
+pub const DEFAULT_CFG: [&str; 2] = [
+    "> @annotate-cfg [clamp = [7, 54]]",
+    "> @annotate-cfg [rangeFn = { start = 8 + start * 3 - 1; end = 8 + end * 3 - 2 }]",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    /// Byte range relative to the start of the dump data
+    pub range: Range<usize>,
+    pub label: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AnnotatedDump {
+    /// Offset of the first byte, as printed in the dump
+    pub base: usize,
+    pub data: Vec<u8>,
+    pub annotations: Vec<Annotation>,
+}
+
+#[derive(Debug, Default)]
+pub struct AnnotatedText {
+    /// `@annotate-cfg` lines
+    pub cfg: Vec<String>,
+    pub dumps: Vec<AnnotatedDump>,
+}
+
+/// A dump being parsed. Positions are absolute (as printed in the dump).
+#[derive(Default)]
+struct PartialDump {
+    bytes: Vec<Option<u8>>,
+    annotations: Vec<Annotation>,
+    last_line_offset: Option<usize>,
+}
+
+impl PartialDump {
+    fn finish(self) -> Result<Option<AnnotatedDump>> {
+        let Some(base) = self.bytes.iter().position(|b| b.is_some()) else {
+            return Ok(None);
+        };
+        let data = self.bytes[base..]
+            .iter()
+            .map(|b| b.context("Gaps in the hex dump"))
+            .collect::<Result<Vec<u8>>>()?;
+        let annotations = self
+            .annotations
+            .into_iter()
+            .map(|a| {
+                if a.range.start < base || a.range.end > base + data.len() {
+                    bail!("Annotation `{}` is out of the dump range", a.label);
+                }
+                Ok(Annotation {
+                    range: (a.range.start - base)..(a.range.end - base),
+                    label: a.label,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Some(AnnotatedDump {
+            base,
+            data,
+            annotations,
+        }))
+    }
+}
+
+/// Parse all annotated dumps in the text. Any line that's neither a dump
+/// line nor an annotation ends the current dump.
+pub fn parse(text: &str) -> Result<AnnotatedText> {
+    let mut ret = AnnotatedText::default();
+    let mut cur = PartialDump::default();
+
+    for (lineno, line) in text.lines().enumerate() {
+        let lineno = lineno + 1;
+        if let Some(cfg) = line.trim().strip_prefix("> @annotate-cfg") {
+            ret.cfg.push(format!("> @annotate-cfg{cfg}"));
+            continue;
+        }
+        if let Some(annotation) = line.trim().strip_prefix("> @annotate") {
+            let Some(line_offset) = cur.last_line_offset else {
+                bail!("Line {lineno}: Annotation without a preceding hex dump line");
+            };
+            let (range, label) =
+                parse_annotation(annotation).with_context(|| format!("Line {lineno}"))?;
+            cur.annotations.push(Annotation {
+                range: (line_offset + range.start)..(line_offset + range.end),
+                label,
+            });
+            continue;
+        }
+        if let Some((offset, bytes)) = hexdump::parse_dump_line(line) {
+            if cur.last_line_offset.is_some_and(|last| offset <= last) {
+                // a new dump
+                ret.dumps.extend(std::mem::take(&mut cur).finish()?);
+            }
+            for (col, byte) in bytes {
+                let pos = offset + col;
+                if cur.bytes.len() <= pos {
+                    cur.bytes.resize(pos + 1, None);
+                }
+                cur.bytes[pos] = Some(byte);
+            }
+            cur.last_line_offset = Some(offset);
+            continue;
+        }
+        ret.dumps.extend(std::mem::take(&mut cur).finish()?);
+    }
+
+    ret.dumps.extend(cur.finish()?);
+    Ok(ret)
+}
+
+/// Parse ` [start-end] label`
+fn parse_annotation(str: &str) -> Result<(Range<usize>, String)> {
+    let str = str.trim_start();
+    let (range, label) = str
+        .strip_prefix('[')
+        .and_then(|s| s.split_once(']'))
+        .context("Expected [start-end]")?;
+    let (start, end) = range.split_once('-').context("Expected [start-end]")?;
+    let start: usize = start.trim().parse()?;
+    let end: usize = end.trim().parse()?;
+    if start >= end || end > 16 {
+        bail!("Invalid range [{start}-{end}]");
+    }
+    Ok((start..end, label.trim().to_string()))
+}
+
+impl AnnotatedDump {
+    /// Annotate the data with a packet layout
+    pub fn from_layout(base: usize, data: Vec<u8>, layout: &[FieldLayout]) -> Self {
+        let annotations = layout
+            .iter()
+            .filter(|f| f.len > 0)
+            .map(|f| Annotation {
+                range: f.range(),
+                label: f.name.to_string(),
+            })
+            .collect();
+        Self {
+            base,
+            data,
+            annotations,
+        }
+    }
+
+    /// Render the dump, splitting the annotations that span multiple lines.
+    pub fn render(&self) -> String {
+        hexdump::render_at(self.base, &self.data, |out, line_offset, range| {
+            for a in &self.annotations {
+                let start = a.range.start.max(range.start);
+                let end = a.range.end.min(range.end);
+                if start >= end {
+                    continue;
+                }
+                let col = |pos: usize| self.base + pos - line_offset;
+                writeln!(out, "> @annotate [{}-{}] {}", col(start), col(end), a.label).unwrap();
+            }
+        })
+    }
+
+    /// Annotations with the same label that directly follow each other
+    /// are merged, so fields split across multiple lines are whole again.
+    pub fn merged_annotations(&self) -> Vec<Annotation> {
+        let mut ret: Vec<Annotation> = Vec::new();
+        for a in &self.annotations {
+            if let Some(last) = ret.last_mut() {
+                if last.label == a.label && last.range.end == a.range.start {
+                    last.range.end = a.range.end;
+                    continue;
+                }
+            }
+            ret.push(a.clone());
+        }
+        ret
+    }
+
+    /// Compare the annotations with a packet layout.
+    /// Returns a human-readable list of differences.
+    pub fn check(&self, layout: &[FieldLayout]) -> Vec<String> {
+        let annotations = self.merged_annotations();
+        let mut diffs = Vec::new();
+
+        for f in layout.iter().filter(|f| f.len > 0) {
+            let range = f.range();
+            match annotations.iter().find(|a| a.range == range) {
+                Some(a) if label_matches(&a.label, f.name) => {}
+                Some(a) => diffs.push(format!(
+                    "{}: field `{}` is annotated as `{}`",
+                    fmt_range(&range),
+                    f.name,
+                    a.label
+                )),
+                None => diffs.push(format!(
+                    "{}: field `{}` is not annotated",
+                    fmt_range(&range),
+                    f.name
+                )),
+            }
+        }
+
+        let is_boundary = |pos: usize| {
+            pos == 0
+                || layout
+                    .iter()
+                    .any(|f| f.offset == pos || f.range().end == pos)
+        };
+        for a in &annotations {
+            if !is_boundary(a.range.start) || !is_boundary(a.range.end) {
+                diffs.push(format!(
+                    "{}: annotation `{}` doesn't match field boundaries",
+                    fmt_range(&a.range),
+                    a.label
+                ));
+            }
+        }
+
+        diffs
+    }
+}
+
+/// The labels are free-form, so only check if the first word is the
+/// field name, e.g. `aux_len (0)` matches `aux_len`
+fn label_matches(label: &str, field: &str) -> bool {
+    let word = label.split_whitespace().next().unwrap_or_default();
+    word.trim_end_matches(['?', ':']) == field
+}
+
+fn fmt_range(range: &Range<usize>) -> String {
+    format!("[{:#x}-{:#x}]", range.start, range.end)
+}
+
+impl AnnotatedText {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for cfg in &self.cfg {
+            writeln!(out, "{cfg}").unwrap();
+        }
+        for dump in &self.dumps {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            out.push_str(&dump.render());
+        }
+        out
+    }
+}
+
+/// Parse, re-render, generate or cross-check `@annotate` hex dumps.
+///
+/// By default, annotated dumps are read and rendered back in a normalized
+/// form. With --generate, the input is decoded as packet(s) and annotated
+/// from the Rust packet definitions. With --check, the existing annotations
+/// are compared against the Rust packet definitions.
+#[derive(Args, Debug)]
+#[command(verbatim_doc_comment)]
+pub struct AnnotateArgs {
+    /// Input file. If not provided, stdin is read instead
+    file: Option<PathBuf>,
+    /// Generate the annotations from packet definitions
+    #[clap(long, conflicts_with = "check")]
+    generate: bool,
+    /// Compare the annotations with packet definitions
+    #[clap(long)]
+    check: bool,
+    /// The headers have no checksum field (e.g. LoginSvr->Client packets)
+    #[clap(long)]
+    no_checksum: bool,
+    /// The dumps contain just the payload, without header, of given ID
+    #[clap(long, value_parser = super::decode::parse_u16)]
+    id: Option<u16>,
+    #[clap(flatten)]
+    ctx: super::decode::ContextArgs,
+}
+
+impl AnnotateArgs {
+    pub fn run(self) -> Result<()> {
+        let input = match &self.file {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Can't read {}", path.display()))?,
+            None => {
+                let mut input = String::new();
+                std::io::stdin().read_to_string(&mut input)?;
+                input
+            }
+        };
+
+        let mut text = parse(&input)?;
+        if text.dumps.is_empty() {
+            // not a dump; try plain hex
+            let data = hexdump::parse(&input)?;
+            text.dumps.push(AnnotatedDump {
+                data,
+                ..Default::default()
+            });
+        }
+
+        if self.generate {
+            for dump in &mut text.dumps {
+                let layout = self.layout(&dump.data)?;
+                *dump =
+                    AnnotatedDump::from_layout(dump.base, std::mem::take(&mut dump.data), &layout);
+            }
+            if text.cfg.is_empty() {
+                text.cfg = DEFAULT_CFG.iter().map(|s| s.to_string()).collect();
+            }
+        }
+
+        if !self.check {
+            print!("{}", text.render());
+            return Ok(());
+        }
+
+        let mut num_diffs = 0;
+        for (idx, dump) in text.dumps.iter().enumerate() {
+            let layout = self.layout(&dump.data)?;
+            let diffs = dump.check(&layout);
+            num_diffs += diffs.len();
+            for diff in diffs {
+                println!("Dump #{idx}: {diff}");
+            }
+        }
+        if num_diffs > 0 {
+            bail!("{num_diffs} difference(s) found");
+        }
+        Ok(())
+    }
+
+    /// Deserialize the data and get its layout, including the header
+    /// if present
+    fn layout(&self, data: &[u8]) -> Result<Vec<FieldLayout>> {
+        if let Some(id) = self.id {
+            let p = Packet::deserialize_no_hdr_ctx(&self.ctx.ctx(), id, data)?;
+            return Ok(p.layout());
+        }
+
+        let checksum = !self.no_checksum;
+        let hdr_len = Header::num_bytes(checksum);
+        let hdr = Header::deserialize(data, checksum)?;
+        if hdr.len as usize > data.len() {
+            bail!(
+                "Incomplete packet ({:#x} bytes, got {:#x})",
+                hdr.len,
+                data.len()
+            );
+        }
+        let payload = &data[hdr_len..hdr.len as usize];
+        let p = Packet::deserialize_no_hdr_ctx(&self.ctx.ctx(), hdr.id, payload)?;
+        let mut layout = vec![
+            FieldLayout {
+                name: "magic",
+                offset: 0,
+                len: 2,
+            },
+            FieldLayout {
+                name: "len",
+                offset: 2,
+                len: 2,
+            },
+        ];
+        if checksum {
+            layout.push(FieldLayout {
+                name: "checksum",
+                offset: 4,
+                len: 4,
+            });
+        }
+        layout.push(FieldLayout {
+            name: "id",
+            offset: hdr_len - 2,
+            len: 2,
+        });
+        layout.extend(p.layout().into_iter().map(|mut f| {
+            f.offset += hdr_len;
+            f
+        }));
+        Ok(layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYSTEM_MESSAGE: &str = "\
+0040                                             00 00   l..J............
+> @annotate [14-16] origin_main_cmd
+0050   00 00 00 00 00 00 00 00 00 00 01 00 00 00 01 00   ................
+> @annotate [0-4] route_hdr
+> @annotate [4-8] unk0
+> @annotate [8-10] unk1
+> @annotate [10-14] unk2
+> @annotate [14-15] msg_type
+> @annotate [15-16] aux_len (0)
+0060   00                                                .
+> @annotate [0-1] trailing
+";
+
+    #[test]
+    fn test_parse_render() {
+        let text = parse(SYSTEM_MESSAGE).unwrap();
+        assert_eq!(text.dumps.len(), 1);
+        let dump = &text.dumps[0];
+        assert_eq!(dump.base, 0x4e);
+        assert_eq!(dump.data.len(), 0x13);
+        assert_eq!(
+            dump.annotations[0],
+            Annotation {
+                range: 0..2,
+                label: "origin_main_cmd".into()
+            }
+        );
+        assert_eq!(dump.annotations.last().unwrap().range, 0x12..0x13);
+
+        let rendered = text.render();
+        assert_eq!(parse(&rendered).unwrap().dumps, text.dumps);
+    }
+
+    #[test]
+    fn test_generate_and_check() {
+        let data: Vec<u8> = (0..0x20).collect();
+        let layout = [
+            FieldLayout {
+                name: "unk1",
+                offset: 0,
+                len: 4,
+            },
+            FieldLayout {
+                name: "long",
+                offset: 4,
+                len: 0x1c,
+            },
+        ];
+        let dump = AnnotatedDump::from_layout(0, data, &layout);
+        let rendered = dump.render();
+        assert!(rendered.contains("> @annotate [4-16] long\n0010"));
+        assert!(rendered.ends_with("> @annotate [0-16] long\n"));
+
+        let parsed = parse(&rendered).unwrap();
+        assert!(parsed.dumps[0].check(&layout).is_empty());
+
+        let mut wrong = parsed.dumps[0].clone();
+        wrong.annotations[0].range = 0..2;
+        let diffs = wrong.check(&layout);
+        assert_eq!(diffs.len(), 2);
+    }
+
+    #[test]
+    fn test_check_packet() {
+        let text = parse(SYSTEM_MESSAGE).unwrap();
+        let dump = &text.dumps[0];
+        let p = Packet::deserialize_no_hdr(packet::pkt_global::SystemMessage::ID, &dump.data);
+        let layout = p.unwrap().layout();
+        let diffs = dump.check(&layout);
+        // the RouteHeader in the original notes is split differently
+        assert_eq!(
+            diffs,
+            [
+                "[0x0-0x6]: field `route_hdr` is not annotated",
+                "[0x0-0x2]: annotation `origin_main_cmd` doesn't match field boundaries",
+                "[0x2-0x6]: annotation `route_hdr` doesn't match field boundaries",
+            ]
+        );
+    }
+}
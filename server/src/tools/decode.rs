@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+use super::hexdump;
+use crate::packet_stream::{PacketDecodeError, PacketDecodeResult, PacketDecoder};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use packet::pkt_common::ServiceID;
+use packet::{FieldLayout, Header, Packet, PacketContext};
+
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Decode packets from a hex dump or a binary file.
+///
+/// The input may contain multiple packets, which are then decoded one
+/// after another. Hex input can be either a Wireshark-like dump, as found
+/// in packet definition comments, or just a list of hex bytes.
+#[derive(Args, Debug)]
+#[command(verbatim_doc_comment)]
+pub struct DecodeArgs {
+    /// Hex input. If not provided, --file or stdin is read instead
+    hex: Option<String>,
+    /// Read the input from a file
+    #[clap(short, long)]
+    file: Option<PathBuf>,
+    /// Treat the input file (or stdin) as raw binary, not hex
+    #[clap(short, long)]
+    binary: bool,
+    /// The headers have no checksum field (e.g. LoginSvr->Client packets)
+    #[clap(long)]
+    no_checksum: bool,
+    /// The input is just a payload without header. Requires --id
+    #[clap(long, requires = "id")]
+    no_header: bool,
+    /// Packet ID for --no-header
+    #[clap(long, value_parser = parse_u16)]
+    id: Option<u16>,
+    #[clap(flatten)]
+    ctx: ContextArgs,
+    /// Decode the XOR-encoded client packets with given table seed.
+    /// The first packet must be the first packet of the connection
+    #[clap(long, value_parser = parse_u32)]
+    xor_seed: Option<u32>,
+    /// Initial key index for --xor-seed
+    #[clap(long, value_parser = parse_u16, default_value = "0", requires = "xor_seed")]
+    xor_key_idx: u16,
+    /// Print the packets as JSON instead of Rust structs
+    #[clap(long)]
+    json: bool,
+    /// Also print the offset and size of each payload field
+    #[clap(long)]
+    layout: bool,
+}
+
+/// Sender and receiver of the packets. Some IDs are used by multiple
+/// packet types, and only these tell them apart
+#[derive(Args, Debug)]
+pub(super) struct ContextArgs {
+    /// Service that sent the packets, e.g. GlobalMgrSvr
+    #[clap(long, value_parser = parse_service)]
+    from: Option<ServiceID>,
+    /// Service that received the packets, e.g. LoginSvr
+    #[clap(long, value_parser = parse_service)]
+    to: Option<ServiceID>,
+}
+
+impl ContextArgs {
+    pub(super) fn ctx(&self) -> PacketContext {
+        PacketContext::new(self.from, self.to)
+    }
+}
+
+fn parse_service(s: &str) -> Result<ServiceID> {
+    (0..=u8::MAX)
+        .filter_map(|id| ServiceID::try_from(id).ok())
+        .find(|service| format!("{service:?}").eq_ignore_ascii_case(s))
+        .with_context(|| format!("Unknown service `{s}`"))
+}
+
+fn parse_u32(s: &str) -> Result<u32, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+pub(super) fn parse_u16(s: &str) -> Result<u16> {
+    Ok(parse_u32(s)?.try_into()?)
+}
+
+impl DecodeArgs {
+    pub fn run(self) -> Result<()> {
+        let mut data = self.read_input()?;
+        if self.no_header {
+            let id = self.id.unwrap();
+            let p = Packet::deserialize_no_hdr_ctx(&self.ctx.ctx(), id, &data)?;
+            self.print_packet(&p)?;
+            return Ok(());
+        }
+
+        let checksum = !self.no_checksum;
+        let mut decoder = self.xor_seed.map(|seed| {
+            let mut decoder = PacketDecoder::new(Some(seed), Some(self.xor_key_idx));
+            decoder.rx_checksum = checksum;
+            decoder
+        });
+
+        let mut data = &mut data[..];
+        while !data.is_empty() {
+            if let Some(decoder) = &mut decoder {
+                match decoder.decode(data) {
+                    Ok(PacketDecodeResult::Done(_)) => {}
+                    Ok(PacketDecodeResult::HeaderIncomplete) => bail!("Incomplete header"),
+                    Ok(PacketDecodeResult::PayloadIncomplete(len)) => {
+                        bail!("Incomplete packet ({len:#x} bytes, got {:#x})", data.len())
+                    }
+                    // the packet is still decoded, so show it anyway
+                    Err(e @ PacketDecodeError::Checksum { .. }) => println!("{e}"),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            let hdr = Header::deserialize(data, checksum)?;
+            println!("{hdr:x?}");
+            let len = hdr.len as usize;
+            if len > data.len() {
+                bail!("Incomplete packet ({len:#x} bytes, got {:#x})", data.len());
+            }
+            let payload = &data[Header::num_bytes(checksum)..len];
+            match Packet::deserialize_no_hdr_ctx(&self.ctx.ctx(), hdr.id, payload) {
+                Ok(p) => self.print_packet(&p)?,
+                Err(e) => {
+                    println!("Can't deserialize: {e}");
+                    print!("{}", hexdump::render(payload));
+                }
+            }
+
+            data = &mut std::mem::take(&mut data)[len..];
+        }
+        Ok(())
+    }
+
+    fn print_packet(&self, p: &Packet) -> Result<()> {
+        match p {
+            p if self.json => println!("{}", serde_json::to_string_pretty(p)?),
+            Packet::Unknown(p) => {
+                println!("Unknown packet {:#x} ({} bytes):", p.id, p.data.len());
+                print!("{}", hexdump::render(&p.data));
+            }
+            p => println!("{p:#?}"),
+        }
+        if self.layout {
+            print_layout(&p.layout());
+        }
+        Ok(())
+    }
+
+    fn read_input(&self) -> Result<Vec<u8>> {
+        let raw = match (&self.hex, &self.file) {
+            (Some(hex), _) => return hexdump::parse(hex),
+            (None, Some(path)) => {
+                std::fs::read(path).with_context(|| format!("Can't read {}", path.display()))?
+            }
+            (None, None) => {
+                let mut raw = Vec::new();
+                std::io::stdin().read_to_end(&mut raw)?;
+                raw
+            }
+        };
+
+        if self.binary {
+            return Ok(raw);
+        }
+        let text = String::from_utf8(raw).context("Input is not a text. Missing --binary?")?;
+        hexdump::parse(&text)
+    }
+}
+
+fn print_layout(layout: &[FieldLayout]) {
+    println!("offset  size  field");
+    for field in layout {
+        println!("{:#06x} {:5}  {}", field.offset, field.len, field.name);
+    }
+}
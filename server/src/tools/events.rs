@@ -0,0 +1,130 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+use crate::event::store::{EventDef, EventStore, EventTarget};
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand};
+use packet::pkt_event::EventType;
+
+use std::path::PathBuf;
+
+/// List, start, or stop EventMgr events.
+///
+/// The events are kept in `resources/events.json` inside the resources
+/// directory. A running EventMgr notices the changes within a second and
+/// sends them to the WorldSvr-s. The events stay there after a restart.
+#[derive(Args, Debug)]
+#[command(verbatim_doc_comment)]
+pub struct EventsArgs {
+    #[arg(default_value = ".")]
+    #[arg(short = 'r', long)]
+    resources_dir: PathBuf,
+    #[clap(subcommand)]
+    cmd: EventsCmd,
+}
+
+#[derive(Subcommand, Debug)]
+#[clap(rename_all = "kebab_case")]
+enum EventsCmd {
+    /// Print all events, including the past ones
+    List,
+    /// Start a new event right now
+    Start {
+        /// Exp, SkillExp, Drop, or AlzDrop
+        #[clap(long = "type", value_parser = parse_event_type)]
+        event_type: EventType,
+        /// In percent, 100 = unchanged
+        #[clap(long)]
+        rate: u32,
+        /// How long the event lasts
+        #[clap(long)]
+        minutes: u32,
+        /// Only send the event to this server. All servers if not specified
+        #[clap(long)]
+        server: Option<u8>,
+        /// Only send the event to this channel of --server
+        #[clap(long, requires = "server")]
+        channel: Option<u8>,
+    },
+    /// End an event right now
+    Stop { id: u32 },
+}
+
+fn parse_event_type(s: &str) -> Result<EventType> {
+    serde_json::from_value(serde_json::Value::String(s.into()))
+        .with_context(|| format!("unknown event type {s}"))
+}
+
+fn unix_time() -> u32 {
+    std::time::UNIX_EPOCH.elapsed().unwrap().as_secs() as u32
+}
+
+impl EventsArgs {
+    pub fn run(self) -> Result<()> {
+        let mut store = EventStore::load(EventStore::path(&self.resources_dir))?;
+        let now = unix_time();
+
+        match self.cmd {
+            EventsCmd::List => {
+                for e in &store.events {
+                    let state = if e.is_active(now) {
+                        "active"
+                    } else if e.start > now {
+                        "scheduled"
+                    } else {
+                        "ended"
+                    };
+                    println!("{state:>9} {}", serde_json::to_string(e)?);
+                }
+            }
+            EventsCmd::Start {
+                event_type,
+                rate,
+                minutes,
+                server,
+                channel,
+            } => {
+                if minutes == 0 {
+                    bail!("--minutes must be greater than 0");
+                }
+                let Some(end) = minutes.checked_mul(60).and_then(|s| now.checked_add(s)) else {
+                    bail!("--minutes is too big, the event would end after year 2106");
+                };
+                let event = EventDef {
+                    id: store.next_id()?,
+                    event_type,
+                    rate,
+                    start: now,
+                    end,
+                    targets: server
+                        .map(|server| EventTarget { server, channel })
+                        .into_iter()
+                        .collect(),
+                };
+                println!("Started event {}", event.id);
+                store.events.push(event);
+                store.save()?;
+            }
+            EventsCmd::Stop { id } => {
+                let Some(idx) = store.events.iter().position(|e| e.id == id) else {
+                    bail!("No event with id {id}");
+                };
+                let event = &mut store.events[idx];
+                if event.end <= now {
+                    bail!("Event {id} has already ended");
+                }
+                if event.start >= now {
+                    // it didn't start yet, so there's nothing to stop
+                    store.events.remove(idx);
+                    println!("Removed event {id}");
+                } else {
+                    event.end = now;
+                    println!("Stopped event {id}");
+                }
+                store.save()?;
+            }
+        }
+        Ok(())
+    }
+}
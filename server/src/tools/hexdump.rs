@@ -0,0 +1,193 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+//! Wireshark-like hex dumps, as pasted in packet definition comments:
+//! ```text
+//! 0000   e2 b7 12 00 00 00 00 00 35 00 01 00 00 00 00 00   ........5.......
+//! 0010   00 00                                             ..
+//! ```
+
+use anyhow::{bail, Context, Result};
+
+use std::fmt::Write;
+use std::ops::Range;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Parse hex bytes out of an arbitrary text.
+///
+/// Wireshark-like dumps are recognized line by line. Their bytes are placed
+/// according to their offset and column, so partial (right-aligned) lines
+/// are handled too. Everything else is parsed as a list of hex bytes, either
+/// contiguous (`e2b7`), separated (`e2 b7`), or prefixed (`0xe2, 0xb7`,
+/// `\xe2\xb7`). Lines starting with `>` or `#` are ignored.
+pub fn parse(text: &str) -> Result<Vec<u8>> {
+    let mut dump: Vec<Option<u8>> = Vec::new();
+    let mut plain = Vec::new();
+
+    for (lineno, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('>') || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if let Some((offset, bytes)) = parse_dump_line(line) {
+            for (idx, byte) in bytes {
+                let pos = offset + idx;
+                if dump.len() <= pos {
+                    dump.resize(pos + 1, None);
+                }
+                dump[pos] = Some(byte);
+            }
+            continue;
+        }
+
+        let bytes = parse_plain(trimmed).with_context(|| format!("Line {}", lineno + 1))?;
+        plain.extend(bytes);
+    }
+
+    if !dump.is_empty() && !plain.is_empty() {
+        bail!("Mixed hex dump lines with plain hex data");
+    }
+    if plain.is_empty() {
+        // the dump may start at any offset, but it can't have any holes
+        let bytes = dump.iter().skip_while(|b| b.is_none());
+        return bytes
+            .map(|b| b.context("Gaps in the hex dump"))
+            .collect::<Result<_>>();
+    }
+    Ok(plain)
+}
+
+/// Try to parse a single `OFFSET   XX XX XX ...   ascii` line.
+/// Returns the line offset and the (column, byte) pairs.
+pub(super) fn parse_dump_line(line: &str) -> Option<(usize, Vec<(usize, u8)>)> {
+    let offset_len = line.find(' ')?;
+    if offset_len < 4 || !line[offset_len..].starts_with("   ") {
+        return None;
+    }
+    let offset = usize::from_str_radix(&line[..offset_len], 16).ok()?;
+    let line = line.as_bytes();
+
+    let mut bytes = Vec::new();
+    for idx in 0..BYTES_PER_LINE {
+        let col = offset_len + 3 + idx * 3;
+        let Some(byte) = line.get(col..col + 2) else {
+            break;
+        };
+        if byte == b"  " {
+            // a partial line
+            continue;
+        }
+        let byte = std::str::from_utf8(byte).ok()?;
+        bytes.push((idx, u8::from_str_radix(byte, 16).ok()?));
+    }
+
+    if bytes.is_empty() {
+        return None;
+    }
+    Some((offset, bytes))
+}
+
+fn parse_plain(line: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for token in line.split(|c: char| c.is_whitespace() || c == ',') {
+        if token.is_empty() {
+            continue;
+        }
+        if let Some(token) = token.strip_prefix("0x") {
+            let byte = u8::from_str_radix(token, 16)
+                .with_context(|| format!("Invalid hex byte `0x{token}`"))?;
+            bytes.push(byte);
+            continue;
+        }
+        let token = token.replace("\\x", "");
+        let decoded =
+            hex::decode(&token).with_context(|| format!("Invalid hex string `{token}`"))?;
+        bytes.extend(decoded);
+    }
+    Ok(bytes)
+}
+
+/// Render bytes in the same format [`parse`] accepts.
+pub fn render(data: &[u8]) -> String {
+    render_at(0, data, |_, _, _| {})
+}
+
+/// Render bytes as if they started at `base` offset. The lines are always
+/// aligned to 16 bytes, so the first one might be partial. `after_line` is
+/// called after each rendered line with the line offset and the range of
+/// `data` it contained, so that extra lines can be appended.
+pub fn render_at(
+    base: usize,
+    data: &[u8],
+    mut after_line: impl FnMut(&mut String, usize, Range<usize>),
+) -> String {
+    let mut out = String::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let line_offset = (base + pos) / BYTES_PER_LINE * BYTES_PER_LINE;
+        let first_col = base + pos - line_offset;
+        let len = (BYTES_PER_LINE - first_col).min(data.len() - pos);
+        render_line(&mut out, line_offset, first_col, &data[pos..pos + len]);
+        after_line(&mut out, line_offset, pos..pos + len);
+        pos += len;
+    }
+    out
+}
+
+fn render_line(out: &mut String, offset: usize, first_col: usize, bytes: &[u8]) {
+    write!(out, "{offset:04x}  ").unwrap();
+    for col in 0..BYTES_PER_LINE {
+        match col.checked_sub(first_col).and_then(|idx| bytes.get(idx)) {
+            Some(b) => write!(out, " {b:02x}").unwrap(),
+            None => out.push_str("   "),
+        }
+    }
+    out.push_str("   ");
+    out.extend(std::iter::repeat_n(' ', first_col));
+    for b in bytes {
+        let c = match b.is_ascii_graphic() || *b == b' ' {
+            true => *b as char,
+            false => '.',
+        };
+        out.push(c);
+    }
+    // no trailing whitespace
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_formats() {
+        let expected = vec![0xe2, 0xb7, 0x0a, 0x00];
+        assert_eq!(parse("e2b70a00").unwrap(), expected);
+        assert_eq!(parse("e2 b7\n0a 00").unwrap(), expected);
+        assert_eq!(parse("0xe2, 0xb7, 0xa, 0x0,").unwrap(), expected);
+        assert_eq!(parse("\\xe2\\xb7\\x0a\\x00").unwrap(), expected);
+        assert!(parse("e2b7a").is_err());
+    }
+
+    #[test]
+    fn test_parse_dump() {
+        let dump = "\
+0040                                             00 01   l..J............
+> @annotate [14-16] origin_main_cmd
+0050   02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11   ................
+0060   12                                                .
+";
+        let data = parse(dump).unwrap();
+        assert_eq!(data, (0..0x13).collect::<Vec<u8>>());
+
+        let rendered = render(&data);
+        assert_eq!(parse(&rendered).unwrap(), data);
+
+        let rendered = render_at(0x4e, &data, |_, _, _| {});
+        assert!(rendered.starts_with(&dump[..54]));
+        assert_eq!(parse(&rendered).unwrap(), data);
+    }
+}
@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+//! Offline tools, ran as `cabalsrv <tool> [OPTIONS]` instead of the
+//! usual `cabalsrv -s <SERVICE>`.
+
+use anyhow::Result;
+use clap::{CommandFactory, Parser, Subcommand};
+
+pub mod annotate;
+pub mod decode;
+#[cfg(feature = "event")]
+pub mod events;
+pub mod hexdump;
+#[cfg(feature = "crypto")]
+pub mod scp;
+
+#[derive(Parser, Debug)]
+#[clap(disable_help_subcommand = true)]
+struct Args {
+    #[clap(subcommand)]
+    tool: Tool,
+}
+
+#[derive(Subcommand, Debug)]
+#[clap(rename_all = "kebab_case")]
+pub enum Tool {
+    Decode(decode::DecodeArgs),
+    Annotate(annotate::AnnotateArgs),
+    #[cfg(feature = "event")]
+    Events(events::EventsArgs),
+    #[cfg(feature = "crypto")]
+    Scp(scp::ScpArgs),
+}
+
+impl Tool {
+    pub fn run(self) -> Result<()> {
+        match self {
+            Tool::Decode(args) => args.run(),
+            Tool::Annotate(args) => args.run(),
+            #[cfg(feature = "event")]
+            Tool::Events(args) => args.run(),
+            #[cfg(feature = "crypto")]
+            Tool::Scp(args) => args.run(),
+        }
+    }
+}
+
+/// Parse the args if they start with a tool name, e.g. `cabalsrv decode`.
+/// Otherwise return None, and the args should be parsed as services.
+pub fn parse_from(args: &[String]) -> Option<Tool> {
+    let name = args.get(1)?;
+    Args::command().find_subcommand(name)?;
+    Some(Args::parse_from(args).tool)
+}
@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+use super::hexdump;
+use crate::crypto::scp::{self, PATH_BLOCKS};
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand};
+use packet::Block;
+
+/// Encrypt or decrypt the SCP paths crypto sends in KeyAuthResponse.
+///
+/// Each path is encrypted with the shortkey of a single WorldSvr
+/// connection. Crypto logs it with RUST_LOG=debug as e.g.
+/// `shortkey=[61, 42, 63, 44, 65, 46, 67, 48]`, and the same format can
+/// be passed to --key.
+///
+/// ESYM files aren't handled yet. That's left open until their encryption
+/// is known, and until then crypto serves them exactly as they are in
+/// resources/esym.
+#[derive(Args, Debug)]
+#[command(verbatim_doc_comment)]
+pub struct ScpArgs {
+    /// Shortkey in hex, up to 32 bytes. The rest is filled with zeroes
+    #[clap(short, long, value_parser = parse_key)]
+    key: aria::Key,
+    #[clap(subcommand)]
+    cmd: ScpCmd,
+}
+
+#[derive(Subcommand, Debug)]
+#[clap(rename_all = "kebab_case")]
+enum ScpCmd {
+    /// Print the encrypted path as a hex dump, e.g. `Data/Item.scp`
+    Encrypt { path: String },
+    /// Print the path decrypted from a hex dump, e.g. the `enc_item` field
+    /// of a decoded KeyAuthResponse
+    Decrypt { hex: String },
+}
+
+fn parse_key(s: &str) -> Result<aria::Key> {
+    // the logged bytes aren't zero-padded, so parse them one by one
+    let tokens: Vec<&str> = s
+        .split(|c: char| c == '[' || c == ']' || c == ',' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+        .collect();
+    let bytes = match tokens.as_slice() {
+        [contiguous] => hexdump::parse(contiguous)?,
+        tokens => tokens
+            .iter()
+            .map(|t| {
+                let t = t.strip_prefix("0x").unwrap_or(t);
+                u8::from_str_radix(t, 16).with_context(|| format!("Invalid hex byte `{t}`"))
+            })
+            .collect::<Result<_>>()?,
+    };
+    if bytes.is_empty() || bytes.len() > 32 {
+        bail!("expected 1 to 32 bytes, got {}", bytes.len());
+    }
+    let mut keybuf = [0u8; 32];
+    keybuf[..bytes.len()].copy_from_slice(&bytes);
+    Ok(aria::Key::from(keybuf))
+}
+
+impl ScpArgs {
+    pub fn run(self) -> Result<()> {
+        match self.cmd {
+            ScpCmd::Encrypt { path } => {
+                let blocks = scp::encrypt_path(&self.key, &path)?;
+                let bytes: Vec<u8> = blocks.iter().flat_map(|b| *b.as_ref()).collect();
+                print!("{}", hexdump::render(&bytes));
+            }
+            ScpCmd::Decrypt { hex } => {
+                let bytes = hexdump::parse(&hex)?;
+                if bytes.len() != PATH_BLOCKS * 16 {
+                    bail!("expected {} bytes, got {}", PATH_BLOCKS * 16, bytes.len());
+                }
+                let blocks: [Block; PATH_BLOCKS] = std::array::from_fn(|i| {
+                    Block::from(<[u8; 16]>::try_from(&bytes[i * 16..(i + 1) * 16]).unwrap())
+                });
+                println!("{}", scp::decrypt_path(&self.key, blocks)?);
+            }
+        }
+        Ok(())
+    }
+}
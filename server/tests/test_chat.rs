@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+use log::trace;
+use packet::pkt_common::{ConnectAck, ServiceID};
+use packet::{BoundVec, Packet};
+use server::executor;
+use server::packet_stream::{IPCPacketStream, KeepaliveConfig, Service};
+
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use smol::{Async, Timer};
+
+const GMS_PORT: u16 = 38178;
+const CHAT_PORT: u16 = 38179;
+
+async fn connect_timeout(port: u16) -> std::io::Result<Async<TcpStream>> {
+    let mut attempts = 0;
+    loop {
+        let conn = Async::<TcpStream>::connect(([127, 0, 0, 1], port)).await;
+        if conn.is_ok() {
+            return conn;
+        }
+
+        attempts += 1;
+        if attempts > 10 {
+            return conn;
+        }
+
+        Timer::after(Duration::from_millis(75)).await;
+    }
+}
+
+/// Accept the chat service's connection like the GMS would, and check
+/// what it registers with
+async fn fake_gms(gms_sock: Async<TcpListener>) -> IPCPacketStream<Async<TcpStream>> {
+    let (stream, _) = gms_sock.accept().await.unwrap();
+    let mut conn = IPCPacketStream::from_host(
+        Service::GlobalMgrSvr { id: 0x80 },
+        stream,
+        KeepaliveConfig::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(conn.other_id, Service::ChatNode);
+
+    #[rustfmt::skip]
+    conn.send(&ConnectAck {
+        bytes: BoundVec(vec![
+            0xff, 0xff, 0xff, 0x7f, 0, 0xff, 0, 0xff,
+            ServiceID::GlobalMgrSvr as u8, 0, 0, 0, 0,
+            0x80, 0xfa, 0, 0, 0, 0, 0x1,
+        ]),
+    })
+    .await
+    .unwrap();
+
+    let p = conn.recv().await.unwrap();
+    let Packet::RegisterChatSvr(register) = &p else {
+        panic!("Expected RegisterChatSvr packet, got {p:?}");
+    };
+    assert_eq!(register.server_id, 2);
+    assert_eq!(register.channel_id, 3);
+    assert_eq!(register.chattype, ServiceID::ChatNode as u8);
+    assert_eq!(register.port, CHAT_PORT);
+    conn
+}
+
+async fn start_client_test(chat: Arc<server::chat::Listener>, gms_sock: Async<TcpListener>) {
+    let _gms = fake_gms(gms_sock).await;
+
+    let mut attempts = 0;
+    while !chat.gms_connected() {
+        attempts += 1;
+        assert!(attempts < 40, "The chat service didn't connect to the GMS");
+        Timer::after(Duration::from_millis(50)).await;
+    }
+
+    trace!("Connecting a WorldSvr ...");
+    let stream = connect_timeout(CHAT_PORT).await.unwrap();
+    let mut world = IPCPacketStream::from_conn(
+        Service::WorldSvr {
+            server: 1,
+            channel: 1,
+        },
+        Service::ChatNode,
+        stream,
+        KeepaliveConfig::default(),
+    )
+    .await
+    .unwrap();
+
+    let p = world.recv().await.unwrap();
+    let Packet::ConnectAck(ack) = &p else {
+        panic!("Expected ConnectAck packet, got {p:?}");
+    };
+    assert_eq!(ack.bytes.get(8), Some(&(ServiceID::ChatNode as u8)));
+}
+
+#[test]
+fn chat_register() {
+    server::setup_log(true);
+
+    executor::run_until(async {
+        let gms_sock = Async::<TcpListener>::bind(([127, 0, 0, 1], GMS_PORT)).unwrap();
+
+        let chat_sock = Async::<TcpListener>::bind(([127, 0, 0, 1], CHAT_PORT)).unwrap();
+        let chat_args = Arc::new(server::args::parse_from_str(&format!(
+            "-s chat --gms-port {GMS_PORT} --server-id 2 --channel-id 3"
+        )));
+        let chat = server::chat::Listener::new(chat_sock, &chat_args);
+        let chat_clone = chat.clone();
+        let chat_t = executor::spawn_local(async move { chat_clone.listen().await });
+
+        start_client_test(chat, gms_sock).await;
+        chat_t.cancel().await;
+    });
+}
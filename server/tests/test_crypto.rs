@@ -3,9 +3,9 @@
 
 use aria::{BlockExt, BlockSlice};
 use log::{info, trace};
-use packet::{Block, Packet, Payload};
+use packet::{Block, Packet};
 use server::executor;
-use server::packet_stream::{IPCPacketStream, Service};
+use server::packet_stream::{IPCPacketStream, KeepaliveConfig, Service};
 
 use std::net::{TcpListener, TcpStream};
 use std::path::PathBuf;
@@ -48,12 +48,14 @@ fn xor_block(mut block: Block) -> Block {
     block
 }
 
-async fn start_client_test() {
+/// Connect and exchange the shortkey
+async fn key_exchange() -> (IPCPacketStream<Async<TcpStream>>, aria::Key) {
     let stream = connect_timeout().await.unwrap();
     let mut conn = IPCPacketStream::from_conn(
         Service::GlobalMgrSvr { id: 0xfd },
         Service::RockNRoll,
         stream,
+        KeepaliveConfig::default(),
     )
     .await
     .unwrap();
@@ -62,10 +64,9 @@ async fn start_client_test() {
     trace!("Waiting for Ack ...");
 
     let p = conn.recv().await.unwrap();
-    let Packet::ConnectAck(ack) = p else {
+    let Packet::CryptoConnectAck(ack) = p else {
         panic!("Expected ConnectAck packet, got {p:?}");
     };
-    let ack = packet::pkt_crypto::ConnectAck::deserialize_no_hdr(&ack.bytes).unwrap();
 
     assert_eq!(ack.unk1, 0x0);
     assert_eq!(ack.unk2, [0x00, 0xff, 0x00, 0xff, 0x00, 0x00, 0x00, 0x00]);
@@ -99,24 +100,29 @@ async fn start_client_test() {
     resp.shortkey.resize(32, 0x0);
 
     trace!("Response received");
-    trace!("Sending Key Auth Request ...");
 
     let keybuf: [u8; 32] = (&resp.shortkey[0..32]).try_into().unwrap();
-    let key = aria::Key::from(keybuf);
+    (conn, aria::Key::from(keybuf))
+}
+
+async fn start_client_test() {
+    let (mut conn, key) = key_exchange().await;
     let enckey = key.expand();
     let deckey = aria::DecryptKey::from(enckey.clone());
 
+    trace!("Sending Key Auth Request ...");
+
     conn.send(&packet::pkt_crypto::KeyAuthRequest {
         unk1: 0x0,
         unk2: 0x0,
         netmask: xor_block(enckey.encrypt(Block::new("255.255.255.127"))),
-        nation: xor_block(enckey.encrypt(Block::new("127.0.0.1"))),
+        nation: xor_block(enckey.encrypt(Block::new("BRA"))),
         srchash: xor_blocks(
             Block::arr_from_slice::<_, 4>("f2b76e1ee8a92a8ce99a41c07926d3f3")
                 .map(|b| enckey.encrypt(b)),
         ),
         binbuf: xor_blocks(Block::arr_from_slice::<_, 4>("empty").map(|b| enckey.encrypt(b))),
-        xor_port: 38180,
+        xor_port: 38180 ^ 0x1f398ab3,
     })
     .await
     .unwrap();
@@ -132,7 +138,7 @@ async fn start_client_test() {
     assert_eq!(resp.unk1, 0x1);
     resp.xor_unk2 ^= 0x1f398ab3;
     assert_eq!(resp.xor_unk2, 0x03010101);
-    assert_eq!(resp.ip_local.try_as_str(), Ok("127.0.0.1"));
+    assert_eq!(resp.ip_local.try_as_str(), Ok("10.0.0.2"));
     resp.xor_unk3 ^= 0xb3;
     assert_eq!(resp.xor_unk3, 0x4);
     xor_blocks_mut(&mut resp.enc_item);
@@ -148,7 +154,7 @@ async fn start_client_test() {
     xor_blocks_mut(&mut resp.enc_warp);
     resp.enc_warp.iter_mut().for_each(|b| deckey.decrypt_mut(b));
     assert_eq!(resp.enc_warp.try_as_str(), Ok("Data/Warp.scp"));
-    assert_eq!(resp.port, 38180);
+    assert_eq!(resp.port, 38181);
 
     trace!("Response received");
     trace!("Sending ESYM Request ...");
@@ -165,21 +171,82 @@ async fn start_client_test() {
     trace!("Waiting for response ...");
 
     let p = conn.recv().await.unwrap();
-    let Packet::ESYM(resp) = p else {
-        panic!("Expected KeyAuthResponse packet, got {p:?}");
+    let Packet::ESYMResponse(resp) = p else {
+        panic!("Expected ESYMResponse packet, got {p:?}");
     };
 
-    let resp = packet::pkt_crypto::ESYMResponse::deserialize_no_hdr(&resp.bytes).unwrap();
     trace!("ESYM resp length: {}", resp.filesize);
     trace!("Reponse received");
 
+    let esym = std::fs::read(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("resources/esym/f2b76e1ee8a92a8ce99a41c07926d3f3.esym"),
+    )
+    .unwrap();
+    assert_eq!(resp.unk1, 0x1);
+    assert_eq!(resp.filesize as usize, esym.len());
+    assert_eq!(resp.esym.0, esym);
+
+    trace!("Sending ESYM Request for an unknown nation ...");
+
+    conn.send(&packet::pkt_crypto::ESYMRequest {
+        unk1: 0x0,
+        nation: "XYZ".into(),
+        srchash: "f2b76e1ee8a92a8ce99a41c07926d3f3".into(),
+    })
+    .await
+    .unwrap();
+
+    let p = conn.recv().await.unwrap();
+    let Packet::ESYMResponse(resp) = p else {
+        panic!("Expected ESYMResponse packet, got {p:?}");
+    };
+    assert_eq!(resp.unk1, 0x0);
+    assert_eq!(resp.filesize, 0);
+    assert!(resp.esym.is_empty());
+
+    trace!("ESYM Request refused");
+
+    trace!("Sending Key Auth Request with a wrong nation ...");
+
+    let (mut conn, key) = key_exchange().await;
+    let enckey = key.expand();
+    conn.send(&packet::pkt_crypto::KeyAuthRequest {
+        unk1: 0x0,
+        unk2: 0x0,
+        netmask: xor_block(enckey.encrypt(Block::new("255.255.255.127"))),
+        nation: xor_block(enckey.encrypt(Block::new("XYZ"))),
+        srchash: xor_blocks(
+            Block::arr_from_slice::<_, 4>("f2b76e1ee8a92a8ce99a41c07926d3f3")
+                .map(|b| enckey.encrypt(b)),
+        ),
+        binbuf: xor_blocks(Block::arr_from_slice::<_, 4>("empty").map(|b| enckey.encrypt(b))),
+        xor_port: 38180 ^ 0x1f398ab3,
+    })
+    .await
+    .unwrap();
+
+    // the connection should be closed without a response
+    let p = conn.recv().await;
+    assert!(
+        p.is_err(),
+        "Expected the connection to be closed, got {p:?}"
+    );
+
+    trace!("Key Auth Request rejected");
+
     info!("All done. Exiting");
 }
 
 async fn start_server() -> Result<()> {
     let tcp_listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 32001)) //
         .expect("Cannot bind to 32001");
-    let mut args = server::args::parse_from_str("-s crypto");
+    let mut args = server::args::parse_from_str(
+        "-s crypto --db-ip 10.0.0.2 --db-port 38181 \
+         --expect-netmask 255.255.255.127 --expect-nation BRA \
+         --expect-srchash f2b76e1ee8a92a8ce99a41c07926d3f3 --expect-port 38180 \
+         --reject-mismatch",
+    );
     args.common.resources_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     let mut listener = server::crypto::Listener::new(tcp_listener, &Arc::new(args));
     listener.listen().await
@@ -3,9 +3,10 @@
 
 use log::{info, trace};
 use packet::pkt_common::Connect;
+use packet::pkt_event::{EventStart, EventStop, EventType};
 use packet::{Packet, Payload};
 use server::executor;
-use server::packet_stream::{IPCPacketStream, Service};
+use server::packet_stream::{IPCPacketStream, KeepaliveConfig, Service};
 
 use std::net::{TcpListener, TcpStream};
 use std::path::PathBuf;
@@ -15,10 +16,10 @@ use std::time::Duration;
 use anyhow::Result;
 use smol::{Async, Timer};
 
-async fn connect_timeout() -> std::io::Result<Async<TcpStream>> {
+async fn connect_timeout(port: u16) -> std::io::Result<Async<TcpStream>> {
     let mut attempts = 0;
     loop {
-        let conn = Async::<TcpStream>::connect(([127, 0, 0, 1], 38171)).await;
+        let conn = Async::<TcpStream>::connect(([127, 0, 0, 1], port)).await;
         if conn.is_ok() {
             return conn;
         }
@@ -33,7 +34,7 @@ async fn connect_timeout() -> std::io::Result<Async<TcpStream>> {
 }
 
 async fn start_client_test() {
-    let stream = connect_timeout().await.unwrap();
+    let stream = connect_timeout(38171).await.unwrap();
     let mut conn = IPCPacketStream::from_conn(
         Service::WorldSvr {
             server: 1,
@@ -41,6 +42,7 @@ async fn start_client_test() {
         },
         Service::EventMgr,
         stream,
+        KeepaliveConfig::default(),
     )
     .await
     .unwrap();
@@ -49,10 +51,9 @@ async fn start_client_test() {
     trace!("Waiting for Ack ...");
 
     let p = conn.recv().await.unwrap();
-    let Packet::ConnectAck(ack) = &p else {
+    let Packet::EventConnectAck(ack) = &p else {
         panic!("Expected ConnectAck packet, got {p:?}");
     };
-    let ack = packet::pkt_event::ConnectAck::deserialize_no_hdr(&ack.bytes).unwrap();
     assert_eq!(ack.unk1, 0x0);
     assert_eq!(
         ack.unk2,
@@ -75,12 +76,15 @@ async fn start_client_test() {
     info!("All done. Exiting");
 }
 
-async fn start_server() -> Result<()> {
-    let tcp_listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 38171)) //
-        .expect("Cannot bind to 38171");
+async fn start_server(port: u16, resources_dir: PathBuf, event_packets: bool) -> Result<()> {
+    let tcp_listener = Async::<TcpListener>::bind(([127, 0, 0, 1], port)) //
+        .unwrap_or_else(|e| panic!("Cannot bind to {port}: {e}"));
 
-    let mut args = server::args::parse_from_str("-s event");
-    args.common.resources_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let mut args = match event_packets {
+        true => server::args::parse_from_str("-s event --experimental-event-packets"),
+        false => server::args::parse_from_str("-s event"),
+    };
+    args.common.resources_dir = resources_dir;
     let mut listener = server::event::Listener::new(tcp_listener, &Arc::new(args));
     listener.listen().await
 }
@@ -90,9 +94,148 @@ fn basic_event_mgr() {
     server::setup_log(true);
 
     executor::run_until(async {
-        let server_t = executor::spawn_local(start_server());
+        let resources_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let server_t = executor::spawn_local(start_server(38171, resources_dir, false));
         let client_f = start_client_test();
         client_f.await;
         server_t.cancel().await;
     });
 }
+
+async fn start_scheduled_client_test() {
+    let stream = connect_timeout(38173).await.unwrap();
+    let mut conn = IPCPacketStream::from_conn(
+        Service::WorldSvr {
+            server: 1,
+            channel: 1,
+        },
+        Service::EventMgr,
+        stream,
+        KeepaliveConfig::default(),
+    )
+    .await
+    .unwrap();
+
+    let p = conn.recv().await.unwrap();
+    assert!(matches!(p, Packet::EventConnectAck(_)));
+
+    // event 2 targets another server, so only event 1 is received
+    let p = conn.recv().await.unwrap();
+    let Packet::Unknown(unknown) = &p else {
+        panic!("Expected an EventStart packet, got {p:?}");
+    };
+    assert_eq!(unknown.id, EventStart::ID);
+    let start = EventStart::deserialize_no_hdr(&unknown.data).unwrap();
+    assert_eq!(start.event_id, 1);
+    assert_eq!(start.event_type, EventType::Drop);
+    assert_eq!(start.rate, 150);
+
+    let p = conn.recv().await.unwrap();
+    let Packet::Unknown(unknown) = &p else {
+        panic!("Expected an EventStop packet, got {p:?}");
+    };
+    assert_eq!(unknown.id, EventStop::ID);
+    let stop = EventStop::deserialize_no_hdr(&unknown.data).unwrap();
+    assert_eq!(stop.event_id, 1);
+
+    info!("All done. Exiting");
+}
+
+#[test]
+fn scheduled_events() {
+    let resources_dir = std::env::temp_dir().join(format!("event_test_{}", std::process::id()));
+    std::fs::create_dir_all(resources_dir.join("resources")).unwrap();
+    let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let events = format!(
+        r#"[
+            {{ "id": 1, "type": "Drop", "rate": 150, "start": {start}, "end": {end},
+               "targets": [{{ "server": 1, "channel": 1 }}] }},
+            {{ "id": 2, "type": "Exp", "rate": 200, "start": {start}, "end": {end},
+               "targets": [{{ "server": 2 }}] }}
+        ]"#,
+        start = now + 2,
+        end = now + 3
+    );
+    std::fs::write(resources_dir.join("resources/events.json"), events).unwrap();
+
+    executor::run_until(async move {
+        let server_t = executor::spawn_local(start_server(38173, resources_dir.clone(), true));
+        start_scheduled_client_test().await;
+        server_t.cancel().await;
+        std::fs::remove_dir_all(resources_dir).unwrap();
+    });
+}
+
+async fn start_admin_client_test(resources_dir: PathBuf) {
+    let stream = connect_timeout(38174).await.unwrap();
+    let mut conn = IPCPacketStream::from_conn(
+        Service::WorldSvr {
+            server: 1,
+            channel: 1,
+        },
+        Service::EventMgr,
+        stream,
+        KeepaliveConfig::default(),
+    )
+    .await
+    .unwrap();
+
+    let p = conn.recv().await.unwrap();
+    assert!(matches!(p, Packet::EventConnectAck(_)));
+
+    // the event was already active when we connected
+    let p = conn.recv().await.unwrap();
+    let Packet::Unknown(unknown) = &p else {
+        panic!("Expected an EventStart packet, got {p:?}");
+    };
+    assert_eq!(unknown.id, EventStart::ID);
+    let start = EventStart::deserialize_no_hdr(&unknown.data).unwrap();
+    assert_eq!(start.event_id, 1);
+
+    // stop it just like an operator would
+    let args = [
+        "cabalsrv",
+        "events",
+        "-r",
+        resources_dir.to_str().unwrap(),
+        "stop",
+        "1",
+    ];
+    let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+    server::tools::parse_from(&args).unwrap().run().unwrap();
+
+    let p = conn.recv().await.unwrap();
+    let Packet::Unknown(unknown) = &p else {
+        panic!("Expected an EventStop packet, got {p:?}");
+    };
+    assert_eq!(unknown.id, EventStop::ID);
+    let stop = EventStop::deserialize_no_hdr(&unknown.data).unwrap();
+    assert_eq!(stop.event_id, 1);
+
+    info!("All done. Exiting");
+}
+
+#[test]
+fn admin_events() {
+    let resources_dir = std::env::temp_dir().join(format!("event_admin_{}", std::process::id()));
+    std::fs::create_dir_all(resources_dir.join("resources")).unwrap();
+    let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let events = format!(
+        r#"[{{ "id": 1, "type": "Exp", "rate": 200, "start": {start}, "end": {end} }}]"#,
+        start = now - 60,
+        end = now + 3600
+    );
+    std::fs::write(resources_dir.join("resources/events.json"), events).unwrap();
+
+    executor::run_until(async move {
+        let server_t = executor::spawn_local(start_server(38174, resources_dir.clone(), true));
+        start_admin_client_test(resources_dir.clone()).await;
+        server_t.cancel().await;
+
+        // the stopped event is persisted
+        let data = std::fs::read_to_string(resources_dir.join("resources/events.json")).unwrap();
+        let events: serde_json::Value = serde_json::from_str(&data).unwrap();
+        assert!(events[0]["end"].as_u64().unwrap() < now + 3600);
+        std::fs::remove_dir_all(resources_dir).unwrap();
+    });
+}
@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+use log::trace;
+use packet::pkt_global::{DuplexRouteHeader, RouteHeader, RoutePacket};
+use packet::{BoundVec, Packet};
+use server::executor;
+use server::packet_stream::{IPCPacketStream, KeepaliveConfig, Service};
+
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use smol::{Async, Timer};
+
+/// Not used by any packet, so it's received as Unknown
+const ROUTED_CMD: u16 = 0x7ff0;
+
+async fn connect_timeout(port: u16) -> std::io::Result<Async<TcpStream>> {
+    let mut attempts = 0;
+    loop {
+        let conn = Async::<TcpStream>::connect(([127, 0, 0, 1], port)).await;
+        if conn.is_ok() {
+            return conn;
+        }
+
+        attempts += 1;
+        if attempts > 10 {
+            return conn;
+        }
+
+        Timer::after(Duration::from_millis(75)).await;
+    }
+}
+
+async fn connect_world(channel: u8) -> IPCPacketStream<Async<TcpStream>> {
+    let stream = connect_timeout(38175).await.unwrap();
+    let mut conn = IPCPacketStream::from_conn(
+        Service::WorldSvr { server: 1, channel },
+        Service::GlobalMgrSvr { id: 0x80 },
+        stream,
+        KeepaliveConfig::default(),
+    )
+    .await
+    .unwrap();
+
+    // ConnectAck, ChangeChannelType, DailyQuestResetTime,
+    // AdditionalDungeonInstanceCount
+    for _ in 0..4 {
+        let p = conn.recv().await.unwrap();
+        trace!("World {channel}: Got {p:?}");
+    }
+    conn
+}
+
+fn route_packet(from_channel: u8, to_channel: u8, data: Vec<u8>) -> RoutePacket {
+    RoutePacket {
+        droute_hdr: DuplexRouteHeader {
+            route_hdr: RouteHeader {
+                origin_main_cmd: ROUTED_CMD,
+                server_id: 1,
+                channel_id: to_channel,
+                world_id: 0,
+                process_id: 0,
+            },
+            unique_idx: 2,
+            to_idx: 3,
+            fm_idx: 4,
+            resp_server_id: 1,
+            resp_channel_id: from_channel,
+            resp_world_id: 0,
+        },
+        data: BoundVec(data),
+    }
+}
+
+async fn start_client_test(listener: Arc<server::gms::Listener>) {
+    let mut world1 = connect_world(1).await;
+    let mut world2 = connect_world(2).await;
+
+    trace!("Routing to a missing channel ...");
+    world1
+        .send(&route_packet(1, 9, vec![0xaa, 0xbb]))
+        .await
+        .unwrap();
+
+    trace!("Routing to an existing channel ...");
+    // world1 should be still connected
+    world1
+        .send(&route_packet(1, 2, vec![0xaa, 0xbb]))
+        .await
+        .unwrap();
+
+    let p = world2.recv().await.unwrap();
+    let Packet::Unknown(routed) = &p else {
+        panic!("Expected an Unknown packet, got {p:?}");
+    };
+    assert_eq!(routed.id, ROUTED_CMD);
+    assert_eq!(&routed.data.0[routed.data.len() - 2..], &[0xaa, 0xbb]);
+
+    // and the failed route wasn't answered
+    world2.send(&route_packet(2, 1, vec![0xcc])).await.unwrap();
+    let p = world1.recv().await.unwrap();
+    let Packet::Unknown(routed) = &p else {
+        panic!("Expected an Unknown packet, got {p:?}");
+    };
+    assert_eq!(routed.data.0.last(), Some(&0xcc));
+
+    assert_eq!(listener.route_stats.routed.load(Ordering::Relaxed), 2);
+    assert_eq!(listener.route_stats.failed.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn route_failure() {
+    server::setup_log(true);
+
+    executor::run_until(async {
+        let tcp_listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 38175)) //
+            .expect("Cannot bind to 38175");
+        let args = server::args::parse_from_str("-s gms");
+        let listener = server::gms::Listener::new(tcp_listener, &Arc::new(args));
+
+        let listener_clone = listener.clone();
+        let server_t = executor::spawn_local(async move { listener_clone.listen().await });
+        start_client_test(listener).await;
+        server_t.cancel().await;
+    });
+}
@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: MIT
+// Copyright(c) 2024 Darek Stojaczyk
+
+use log::info;
+use packet::pkt_global::DailyQuestResetTime;
+use server::executor;
+use server::packet_stream::{PacketStream, StreamConfig};
+use server::replay::Replay;
+
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use smol::{Async, Timer};
+
+async fn connect_timeout(port: u16) -> std::io::Result<Async<TcpStream>> {
+    let mut attempts = 0;
+    loop {
+        let conn = Async::<TcpStream>::connect(([127, 0, 0, 1], port)).await;
+        if conn.is_ok() {
+            return conn;
+        }
+
+        attempts += 1;
+        if attempts > 10 {
+            return conn;
+        }
+
+        Timer::after(Duration::from_millis(75)).await;
+    }
+}
+
+/// Replay one of `tests/recordings`. These were recorded against our own
+/// services or written by hand, so they're smoke tests of the replay
+/// driver rather than regression tests against the original services.
+/// Proxy captures of the original WorldSvr traffic can be dropped there
+/// the same way
+async fn start_replay(recording: &str, port: u16, ignore_payload: &[u16]) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/recordings")
+        .join(recording);
+    let entries = server::packet_log::read_file(&path).unwrap();
+    let mut replay = Replay::new(entries);
+    replay.ignore_payload.extend(ignore_payload);
+
+    let stream = connect_timeout(port).await.unwrap();
+    let mut stream = PacketStream::new(stream, StreamConfig::ipc("Replay".into(), "?".into()));
+    let mismatches = replay.run(&mut stream).await.unwrap();
+    for m in &mismatches {
+        println!("{m}");
+    }
+    assert!(mismatches.is_empty());
+
+    info!("All done. Exiting");
+}
+
+async fn start_event_server() -> Result<()> {
+    let tcp_listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 38172)) //
+        .expect("Cannot bind to 38172");
+
+    let mut args = server::args::parse_from_str("-s event");
+    args.common.resources_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let mut listener = server::event::Listener::new(tcp_listener, &Arc::new(args));
+    listener.listen().await
+}
+
+#[test]
+fn replay_event_worldsvr() {
+    server::setup_log(true);
+
+    executor::run_until(async {
+        let server_t = executor::spawn_local(start_event_server());
+        start_replay("event_worldsvr.log", 38172, &[]).await;
+        server_t.cancel().await;
+    });
+}
+
+async fn start_gms_server() -> Result<()> {
+    let tcp_listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 38183)) //
+        .expect("Cannot bind to 38183");
+
+    let args = server::args::parse_from_str("-s gms");
+    let listener = server::gms::Listener::new(tcp_listener, &Arc::new(args));
+    listener.listen().await
+}
+
+#[test]
+fn replay_gms_worldsvr() {
+    executor::run_until(async {
+        let server_t = executor::spawn_local(start_gms_server());
+        start_replay("gms_worldsvr.log", 38183, &[DailyQuestResetTime::ID]).await;
+        server_t.cancel().await;
+    });
+}